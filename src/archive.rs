@@ -0,0 +1,919 @@
+//! A writer for static archives, used instead of `ar` when it is missing or
+//! when `AR=builtin` is set, and a reader to extract the members of the
+//! archives merged into a library.
+//!
+//! The archives are always deterministic, and include a symbol table listing
+//! the global symbols defined by the ELF, Mach-O and COFF objects in them, so
+//! that no `ranlib` step is needed. Other members, such as LLVM bitcode, are
+//! added without their symbols.
+
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The flavour of archive to write, depending on the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ArchiveFormat {
+    /// The System V format used by GNU `ar`, with a `/` symbol table and a
+    /// `//` table for long member names.
+    Gnu,
+    /// The BSD format used by Apple's `ar`, with a `__.SYMDEF` symbol table
+    /// and the long member names after each header.
+    Darwin,
+    /// The format used by `lib.exe`, like the GNU one with a second, sorted,
+    /// symbol table.
+    Coff,
+}
+
+/// A member of an archive, with the symbols it defines.
+struct Member {
+    name: Vec<u8>,
+    data: Vec<u8>,
+    symbols: Vec<Vec<u8>>,
+}
+
+/// Write the archive `dst`, containing the files `members`, in `format`.
+pub(crate) fn write(dst: &Path, members: &[&Path], format: ArchiveFormat) -> io::Result<()> {
+    let members = members
+        .iter()
+        .map(|path| {
+            let data = fs::read(path)?;
+            let name = path
+                .file_name()
+                .map_or_else(Vec::new, |name| name.to_string_lossy().into_owned().into());
+            Ok(Member {
+                symbols: symbols(&data),
+                name,
+                data,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut archive = Vec::new();
+    match format {
+        ArchiveFormat::Gnu | ArchiveFormat::Coff => write_gnu(&mut archive, &members, format)?,
+        ArchiveFormat::Darwin => write_darwin(&mut archive, &members)?,
+    }
+    fs::write(dst, archive)
+}
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const HEADER_LEN: usize = 60;
+
+/// The date, uid, gid and mode of the objects, zero but for the mode to be
+/// deterministic.
+const MEMBER: [&[u8]; 4] = [b"0", b"0", b"0", b"644"];
+/// The date, uid, gid and mode of the symbol tables.
+const SYMBOL_TABLE: [&[u8]; 4] = [b"0"; 4];
+/// The date, uid, gid and mode of the GNU table of long names, left blank.
+const LONG_NAMES: [&[u8]; 4] = [b""; 4];
+
+/// Write the header of a member, with the name field `name`, the date, uid,
+/// gid and mode `fields` and `size` bytes of data.
+fn header(out: &mut Vec<u8>, name: &[u8], fields: [&[u8]; 4], size: usize) -> io::Result<()> {
+    let field = |out: &mut Vec<u8>, value: &[u8], len: usize| -> io::Result<()> {
+        if value.len() > len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "archive member too large",
+            ));
+        }
+        out.extend_from_slice(value);
+        out.resize(out.len() + len - value.len(), b' ');
+        Ok(())
+    };
+    field(out, name, 16)?;
+    field(out, fields[0], 12)?;
+    field(out, fields[1], 6)?;
+    field(out, fields[2], 6)?;
+    field(out, fields[3], 8)?;
+    field(out, size.to_string().as_bytes(), 10)?;
+    out.extend_from_slice(b"`\n");
+    Ok(())
+}
+
+fn offset_u32(offset: usize) -> io::Result<u32> {
+    u32::try_from(offset).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "archive larger than 4 GiB, which the symbol table can't address",
+        )
+    })
+}
+
+fn write_gnu(out: &mut Vec<u8>, members: &[Member], format: ArchiveFormat) -> io::Result<()> {
+    // Names longer than 15 bytes (with the `/` terminator) go in the `//`
+    // member, and are referred to by their offset in it.
+    let mut long_names = Vec::new();
+    let names: Vec<Vec<u8>> = members
+        .iter()
+        .map(|member| {
+            if member.name.len() < 16 && !member.name.contains(&b'/') {
+                let mut name = member.name.clone();
+                name.push(b'/');
+                name
+            } else {
+                let name = format!("/{}", long_names.len()).into_bytes();
+                long_names.extend_from_slice(&member.name);
+                // `lib.exe` terminates the names with a NUL, GNU `ar` with `/\n`.
+                if format == ArchiveFormat::Coff {
+                    long_names.push(0);
+                } else {
+                    long_names.extend_from_slice(b"/\n");
+                }
+                name
+            }
+        })
+        .collect();
+    // GNU `ar` counts the padding in the size of the table.
+    pad(&mut long_names, 2);
+
+    let symbol_count: usize = members.iter().map(|member| member.symbols.len()).sum();
+    let names_len: usize = members
+        .iter()
+        .flat_map(|member| &member.symbols)
+        .map(|symbol| symbol.len() + 1)
+        .sum();
+    let padded = |len: usize| len + len % 2;
+    let first_len = 4 + 4 * symbol_count + names_len;
+    let second_len = 4 + 4 * members.len() + 4 + 2 * symbol_count + names_len;
+
+    // Compute where each member starts, to point the symbols at them.
+    let mut offset = MAGIC.len() + HEADER_LEN + padded(first_len);
+    if format == ArchiveFormat::Coff {
+        offset += HEADER_LEN + padded(second_len);
+    }
+    if !long_names.is_empty() {
+        offset += HEADER_LEN + long_names.len();
+    }
+    let mut offsets = Vec::with_capacity(members.len());
+    for member in members {
+        offsets.push(offset_u32(offset)?);
+        offset += HEADER_LEN + padded(member.data.len());
+    }
+
+    out.extend_from_slice(MAGIC);
+
+    // The first symbol table: the members' offsets (big-endian) for each
+    // symbol, in the order of the members, followed by the names.
+    header(out, b"/", SYMBOL_TABLE, first_len)?;
+    out.extend_from_slice(&offset_u32(symbol_count)?.to_be_bytes());
+    for (member, offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            out.extend_from_slice(&offset.to_be_bytes());
+        }
+    }
+    for symbol in members.iter().flat_map(|member| &member.symbols) {
+        out.extend_from_slice(symbol);
+        out.push(0);
+    }
+    pad(out, 2);
+
+    // The second symbol table of `lib.exe`: the members' offsets
+    // (little-endian), then the names in sorted order with the (1-based)
+    // index of their member.
+    if format == ArchiveFormat::Coff {
+        let mut symbols: Vec<(&[u8], u16)> = Vec::with_capacity(symbol_count);
+        for (i, member) in members.iter().enumerate() {
+            let index = u16::try_from(i + 1).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "too many archive members for the symbol table",
+                )
+            })?;
+            symbols.extend(member.symbols.iter().map(|symbol| (&symbol[..], index)));
+        }
+        symbols.sort();
+
+        header(out, b"/", SYMBOL_TABLE, second_len)?;
+        out.extend_from_slice(&offset_u32(members.len())?.to_le_bytes());
+        for offset in &offsets {
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        out.extend_from_slice(&offset_u32(symbol_count)?.to_le_bytes());
+        for (_, index) in &symbols {
+            out.extend_from_slice(&index.to_le_bytes());
+        }
+        for (symbol, _) in &symbols {
+            out.extend_from_slice(symbol);
+            out.push(0);
+        }
+        pad(out, 2);
+    }
+
+    if !long_names.is_empty() {
+        header(out, b"//", LONG_NAMES, long_names.len())?;
+        out.extend_from_slice(&long_names);
+    }
+
+    for (member, name) in members.iter().zip(&names) {
+        header(out, name, MEMBER, member.data.len())?;
+        out.extend_from_slice(&member.data);
+        pad(out, 2);
+    }
+    Ok(())
+}
+
+fn write_darwin(out: &mut Vec<u8>, members: &[Member]) -> io::Result<()> {
+    // Every name follows its header, as `#1/<length>`, padded with NULs so
+    // that the data is 8-byte aligned, as `ld64` expects. The data itself is
+    // padded to 8 bytes too, which the size in the header includes.
+    fn padded_name(name: &[u8], pos: usize) -> Vec<u8> {
+        let mut name = name.to_vec();
+        while (pos + HEADER_LEN + name.len()) % 8 != 0 {
+            name.push(0);
+        }
+        name
+    }
+    fn member(out: &mut Vec<u8>, name: &[u8], fields: [&[u8]; 4], data: &[u8]) -> io::Result<()> {
+        let name = padded_name(name, out.len());
+        let size = name.len() + data.len() + (8 - data.len() % 8) % 8;
+        header(out, format!("#1/{}", name.len()).as_bytes(), fields, size)?;
+        out.extend_from_slice(&name);
+        out.extend_from_slice(data);
+        pad(out, 8);
+        Ok(())
+    }
+    let member_len = |name: &[u8], pos: usize, data_len: usize| {
+        HEADER_LEN + padded_name(name, pos).len() + data_len + (8 - data_len % 8) % 8
+    };
+
+    let mut strings = Vec::new();
+    let mut string_offsets = Vec::new();
+    for symbol in members.iter().flat_map(|member| &member.symbols) {
+        string_offsets.push(offset_u32(strings.len())?);
+        strings.extend_from_slice(symbol);
+        strings.push(0);
+    }
+    let symbol_count = string_offsets.len();
+    let symdef_len = 4 + 8 * symbol_count + 4 + strings.len();
+
+    let symdef_name = b"__.SYMDEF";
+    let mut offset = MAGIC.len() + member_len(symdef_name, MAGIC.len(), symdef_len);
+    let mut offsets = Vec::with_capacity(members.len());
+    for member in members {
+        offsets.push(offset_u32(offset)?);
+        offset += member_len(&member.name, offset, member.data.len());
+    }
+
+    // The symbol table: pairs of the offset of the name and the offset of the
+    // member, then the names, all little-endian.
+    let mut symdef = Vec::with_capacity(symdef_len);
+    symdef.extend_from_slice(&offset_u32(8 * symbol_count)?.to_le_bytes());
+    let mut string_offsets = string_offsets.iter();
+    for (member, offset) in members.iter().zip(&offsets) {
+        for (_, string_offset) in member.symbols.iter().zip(&mut string_offsets) {
+            symdef.extend_from_slice(&string_offset.to_le_bytes());
+            symdef.extend_from_slice(&offset.to_le_bytes());
+        }
+    }
+    symdef.extend_from_slice(&offset_u32(strings.len())?.to_le_bytes());
+    symdef.extend_from_slice(&strings);
+
+    out.extend_from_slice(MAGIC);
+    member(out, symdef_name, SYMBOL_TABLE, &symdef)?;
+    for m in members {
+        member(out, &m.name, MEMBER, &m.data)?;
+    }
+    Ok(())
+}
+
+/// Pad `out` with newlines (NULs for 8-byte alignment, as in Darwin's symbol
+/// table and members) to a multiple of `align` bytes.
+fn pad(out: &mut Vec<u8>, align: usize) {
+    let byte = if align == 2 { b'\n' } else { 0 };
+    while out.len() % align != 0 {
+        out.push(byte);
+    }
+}
+
+/// Extract the members of the archive `src` into the directory `dir`, and
+/// return their paths.
+///
+/// Each member is named after its file name in the archive, made unique with
+/// [`unique_name`], so that members with the same name don't overwrite each
+/// other or clash in the library they are merged into.
+pub(crate) fn extract(
+    src: &Path,
+    dir: &Path,
+    taken: &mut HashSet<String>,
+) -> io::Result<Vec<PathBuf>> {
+    let data = fs::read(src)?;
+    let members = read(&data)?;
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::with_capacity(members.len());
+    for (name, member) in members {
+        let name = String::from_utf8_lossy(name);
+        // `lib.exe` keeps the paths of the objects.
+        let name = match name.rsplit(['/', '\\']).next() {
+            Some(name) if !name.is_empty() && name != "." && name != ".." => name,
+            _ => "member.o",
+        };
+        let path = dir.join(unique_name(name, taken));
+        fs::write(&path, member)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// The file name `name`, with a number added if it is in `taken` (ignoring
+/// case), which it is then added to.
+pub(crate) fn unique_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut unique = name.to_owned();
+    let mut i = 1;
+    while !taken.insert(unique.to_lowercase()) {
+        unique = format!("{}-{}{}", stem, i, extension);
+        i += 1;
+    }
+    unique
+}
+
+/// The name and data of the members of the archive `data`, in any of the
+/// formats above, leaving out the symbol tables.
+fn read(data: &[u8]) -> io::Result<Vec<(&[u8], &[u8])>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if data.starts_with(b"!<thin>\n") {
+        return Err(invalid("thin archives can't be merged"));
+    }
+    if !data.starts_with(MAGIC) {
+        return Err(invalid("not an archive"));
+    }
+    let number = |field: &[u8]| {
+        std::str::from_utf8(field)
+            .ok()
+            .and_then(|field| field.trim_end().parse::<usize>().ok())
+            .ok_or_else(|| invalid("malformed archive member header"))
+    };
+
+    let mut members = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut pos = MAGIC.len();
+    while pos + HEADER_LEN <= data.len() {
+        let header = &data[pos..pos + HEADER_LEN];
+        let start = pos + HEADER_LEN;
+        let size = number(&header[48..58])?;
+        let member = match data.get(start..start + size) {
+            Some(member) if header[58..] == *b"`\n" => member,
+            _ => return Err(invalid("malformed archive member header")),
+        };
+        pos = start + size + size % 2;
+
+        let name = trim_end(&header[..16], b' ');
+        let (name, member) = if let Some(len) = name.strip_prefix(b"#1/") {
+            // A BSD name, following the header.
+            let len = number(len)?;
+            if len > member.len() {
+                return Err(invalid("malformed archive member header"));
+            }
+            let (name, member) = member.split_at(len);
+            (trim_end(name, 0), member)
+        } else if name == b"/"
+            || name == b"/SYM64/"
+            // Other tables of COFF archives, such as `/<ECSYMBOLS>/` or
+            // `/<XFGHASHMAP>/`.
+            || (name.starts_with(b"/<") && name.ends_with(b">/"))
+        {
+            continue;
+        } else if name == b"//" {
+            long_names = member;
+            continue;
+        } else if let Some(offset) = name.strip_prefix(b"/") {
+            // A GNU or COFF name in the long names, ending with `/\n` or NUL.
+            let name = long_names
+                .get(number(offset)?..)
+                .ok_or_else(|| invalid("malformed archive member name"))?;
+            let end = name
+                .iter()
+                .position(|&b| b == 0 || b == b'\n')
+                .unwrap_or(name.len());
+            (trim_end(&name[..end], b'/'), member)
+        } else {
+            (trim_end(name, b'/'), member)
+        };
+        if name.starts_with(b"__.SYMDEF") {
+            continue;
+        }
+        members.push((name, member));
+    }
+    Ok(members)
+}
+
+/// `field` without the trailing `byte`s.
+fn trim_end(mut field: &[u8], byte: u8) -> &[u8] {
+    while let Some((&last, rest)) = field.split_last() {
+        if last != byte {
+            break;
+        }
+        field = rest;
+    }
+    field
+}
+
+/// The global symbols defined by the object file `data`, or none if it isn't
+/// an ELF, Mach-O or COFF object.
+pub(crate) fn symbols(data: &[u8]) -> Vec<Vec<u8>> {
+    let symbols = if data.starts_with(b"\x7fELF") {
+        elf_symbols(data)
+    } else if data.starts_with(&[0xce, 0xfa, 0xed, 0xfe])
+        || data.starts_with(&[0xcf, 0xfa, 0xed, 0xfe])
+    {
+        macho_symbols(data)
+    } else {
+        coff_symbols(data)
+    };
+    symbols.unwrap_or_default()
+}
+
+/// Read fixed-size integers out of an object file, failing on truncated
+/// input.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(offset..offset.checked_add(len)?)
+    }
+
+    fn u8(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes(offset, 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.bytes(offset, 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Option<u64> {
+        let bytes = self.bytes(offset, 8)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// A 32-bit value, or a 64-bit one if `wide`, as a `usize`.
+    fn word(&self, offset: usize, wide: bool) -> Option<usize> {
+        if wide {
+            self.u64(offset)?.try_into().ok()
+        } else {
+            self.u32(offset)?.try_into().ok()
+        }
+    }
+
+    /// The NUL-terminated string at `offset`.
+    fn c_str(&self, offset: usize) -> Option<&'a [u8]> {
+        let rest = self.data.get(offset..)?;
+        let len = rest.iter().position(|&b| b == 0)?;
+        Some(&rest[..len])
+    }
+}
+
+fn elf_symbols(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    const SHT_SYMTAB: u32 = 2;
+    const SHN_UNDEF: u16 = 0;
+    const STB_GLOBAL: u8 = 1;
+    const STB_WEAK: u8 = 2;
+    const STB_GNU_UNIQUE: u8 = 10;
+
+    let wide = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let r = Reader {
+        data,
+        big_endian: *data.get(5)? == 2,
+    };
+    let (shoff, shentsize, shnum) = if wide {
+        (r.word(0x28, true)?, r.u16(0x3a)?, r.u16(0x3c)?)
+    } else {
+        (r.word(0x20, false)?, r.u16(0x2e)?, r.u16(0x30)?)
+    };
+    let section = |i: usize| shoff.checked_add(i.checked_mul(usize::from(shentsize))?);
+    // (offset, size, link) of a section header.
+    let section_info = |header: usize| -> Option<(usize, usize, usize)> {
+        if wide {
+            Some((
+                r.word(header + 24, true)?,
+                r.word(header + 32, true)?,
+                r.u32(header + 40)? as usize,
+            ))
+        } else {
+            Some((
+                r.word(header + 16, false)?,
+                r.word(header + 20, false)?,
+                r.u32(header + 24)? as usize,
+            ))
+        }
+    };
+    // With more sections than fit in `e_shnum`, their number is in the size
+    // of the first section header.
+    let shnum = match shnum {
+        0 => section_info(section(0)?)?.1,
+        n => usize::from(n),
+    };
+
+    let mut symbols = Vec::new();
+    for i in 0..shnum {
+        let header = section(i)?;
+        if r.u32(header + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let (offset, size, link) = section_info(header)?;
+        let (strtab, _, _) = section_info(section(link)?)?;
+        let entsize = if wide { 24 } else { 16 };
+        for sym in (offset..offset.checked_add(size)?).step_by(entsize).skip(1) {
+            let (info, shndx) = if wide {
+                (r.u8(sym + 4)?, r.u16(sym + 6)?)
+            } else {
+                (r.u8(sym + 12)?, r.u16(sym + 14)?)
+            };
+            if matches!(info >> 4, STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE) && shndx != SHN_UNDEF {
+                let name = r.c_str(strtab.checked_add(r.u32(sym)? as usize)?)?;
+                symbols.push(name.to_vec());
+            }
+        }
+    }
+    Some(symbols)
+}
+
+/// A minimal little-endian ELF64 object with a symbol table of `(name, info,
+/// section index)` entries, for the tests of this module and of the symbol
+/// renaming.
+#[doc(hidden)]
+pub fn __elf_object(symbols: &[(&str, u8, u16)]) -> Vec<u8> {
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; 24];
+    for &(name, info, shndx) in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+        symtab.push(info);
+        symtab.push(0);
+        symtab.extend_from_slice(&shndx.to_le_bytes());
+        symtab.extend_from_slice(&[0; 16]);
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+
+    let mut data = vec![0; 64];
+    data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    let symtab_offset = data.len();
+    data.extend_from_slice(&symtab);
+    let strtab_offset = data.len();
+    data.extend_from_slice(&strtab);
+    let shoff = data.len();
+    data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+    data[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+    data[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+    // The null section, the symbol table and the string table.
+    data.extend_from_slice(&[0; 64]);
+    for (kind, offset, size, link) in [
+        (2u32, symtab_offset, symtab.len(), 2u32),
+        (3, strtab_offset, strtab.len(), 0),
+    ] {
+        let mut header = [0; 64];
+        header[4..8].copy_from_slice(&kind.to_le_bytes());
+        header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+        header[40..44].copy_from_slice(&link.to_le_bytes());
+        data.extend_from_slice(&header);
+    }
+    data
+}
+
+fn macho_symbols(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    const LC_SYMTAB: u32 = 0x2;
+    const N_STAB: u8 = 0xe0;
+    const N_TYPE: u8 = 0x0e;
+    const N_EXT: u8 = 0x01;
+    const N_UNDF: u8 = 0x0;
+
+    let r = Reader {
+        data,
+        big_endian: false,
+    };
+    let wide = r.u32(0)? == 0xfeed_facf;
+    let ncmds = r.u32(16)?;
+    let mut cmd = if wide { 32 } else { 28 };
+    for _ in 0..ncmds {
+        if r.u32(cmd)? == LC_SYMTAB {
+            let symoff = r.u32(cmd + 8)? as usize;
+            let nsyms = r.u32(cmd + 12)? as usize;
+            let stroff = r.u32(cmd + 16)? as usize;
+            let entsize = if wide { 16 } else { 12 };
+            let mut symbols = Vec::new();
+            for i in 0..nsyms {
+                let sym = symoff.checked_add(i.checked_mul(entsize)?)?;
+                let kind = r.u8(sym + 4)?;
+                // Common symbols are undefined, with their size as value.
+                let common = r.word(sym + 8, wide)? != 0;
+                if kind & N_STAB == 0 && kind & N_EXT != 0 && (kind & N_TYPE != N_UNDF || common) {
+                    let name = r.c_str(stroff.checked_add(r.u32(sym)? as usize)?)?;
+                    symbols.push(name.to_vec());
+                }
+            }
+            return Some(symbols);
+        }
+        cmd = cmd.checked_add(r.u32(cmd + 4)? as usize)?;
+    }
+    Some(Vec::new())
+}
+
+fn coff_symbols(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    const IMAGE_SYM_CLASS_EXTERNAL: u8 = 2;
+
+    let r = Reader {
+        data,
+        big_endian: false,
+    };
+    // Regular objects have 18-byte symbols with a 16-bit section number,
+    // `/bigobj` ones 20-byte symbols with a 32-bit one.
+    let big = r.u16(0)? == 0 && r.u16(2)? == 0xffff;
+    let (machine, symtab, nsyms, entsize) = if big {
+        (r.u16(6)?, r.u32(48)?, r.u32(52)?, 20)
+    } else {
+        if r.u16(16)? != 0 {
+            // Not an object, but an image with an optional header.
+            return None;
+        }
+        (r.u16(0)?, r.u32(8)?, r.u32(12)?, 18)
+    };
+    // i386, x86_64, ARMv7, AArch64 and ARM64EC.
+    if !matches!(machine, 0x14c | 0x8664 | 0x1c4 | 0xaa64 | 0xa641) {
+        return None;
+    }
+    let symtab = symtab as usize;
+    let strtab = symtab.checked_add((nsyms as usize).checked_mul(entsize)?)?;
+
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < nsyms as usize {
+        let sym = symtab + i * entsize;
+        let value = r.u32(sym + 8)?;
+        let (section, class, aux) = if big {
+            (r.u32(sym + 12)? as i32, r.u8(sym + 18)?, r.u8(sym + 19)?)
+        } else {
+            (
+                i32::from(r.u16(sym + 12)? as i16),
+                r.u8(sym + 16)?,
+                r.u8(sym + 17)?,
+            )
+        };
+        // Defined in a section, absolute, or common (undefined with a size).
+        if class == IMAGE_SYM_CLASS_EXTERNAL && (section != 0 || value != 0) {
+            let name = if r.u32(sym)? == 0 {
+                r.c_str(strtab.checked_add(r.u32(sym + 4)? as usize)?)?
+            } else {
+                let name = r.bytes(sym, 8)?;
+                &name[..name.iter().position(|&b| b == 0).unwrap_or(8)]
+            };
+            symbols.push(name.to_vec());
+        }
+        i += 1 + usize::from(aux);
+    }
+    Some(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name, offset and data of a member.
+    type Parsed = (String, usize, Vec<u8>);
+
+    /// The members of a GNU or COFF archive, and its first symbol table with
+    /// the offset of the member of each symbol.
+    fn parse_gnu(archive: &[u8]) -> (Vec<Parsed>, Vec<(String, usize)>) {
+        assert!(archive.starts_with(MAGIC));
+        let mut members = Vec::new();
+        let mut pos = MAGIC.len();
+        let mut long_names = Vec::new();
+        let mut symbols = Vec::new();
+        while pos < archive.len() {
+            let header = &archive[pos..pos + HEADER_LEN];
+            assert_eq!(&header[58..], b"`\n");
+            let name = std::str::from_utf8(&header[..16]).unwrap().trim_end();
+            let fields = match name {
+                "/" => SYMBOL_TABLE,
+                "//" => LONG_NAMES,
+                _ => MEMBER,
+            };
+            let fields = fields.map(|f| std::str::from_utf8(f).unwrap());
+            assert_eq!(
+                &header[16..48],
+                format!(
+                    "{:<12}{:<6}{:<6}{:<8}",
+                    fields[0], fields[1], fields[2], fields[3]
+                )
+                .as_bytes()
+            );
+            let size: usize = std::str::from_utf8(&header[48..58])
+                .unwrap()
+                .trim_end()
+                .parse()
+                .unwrap();
+            let data = &archive[pos + HEADER_LEN..pos + HEADER_LEN + size];
+            if name == "/" && symbols.is_empty() {
+                let count = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+                let mut names = data[4 + 4 * count..].split(|&b| b == 0);
+                for i in 0..count {
+                    let offset = u32::from_be_bytes(data[4 + 4 * i..8 + 4 * i].try_into().unwrap());
+                    let name = String::from_utf8(names.next().unwrap().to_vec()).unwrap();
+                    symbols.push((name, offset as usize));
+                }
+            } else if name == "//" {
+                long_names = data.to_vec();
+            } else if name != "/" {
+                let name = match name.strip_prefix('/') {
+                    Some(offset) => {
+                        let rest = &long_names[offset.parse::<usize>().unwrap()..];
+                        let end = rest.iter().position(|&b| b == 0 || b == b'/').unwrap();
+                        String::from_utf8(rest[..end].to_vec()).unwrap()
+                    }
+                    None => name.strip_suffix('/').unwrap().to_owned(),
+                };
+                members.push((name, pos, data.to_vec()));
+            }
+            pos += HEADER_LEN + size + size % 2;
+        }
+        (members, symbols)
+    }
+
+    fn members() -> Vec<Member> {
+        vec![
+            Member {
+                name: b"a.o".to_vec(),
+                data: b"abc".to_vec(),
+                symbols: vec![b"foo".to_vec(), b"bar".to_vec()],
+            },
+            Member {
+                name: b"0123456789abcdef-long.o".to_vec(),
+                data: b"defg".to_vec(),
+                symbols: vec![b"baz".to_vec()],
+            },
+        ]
+    }
+
+    #[test]
+    fn elf() {
+        let data = __elf_object(&[
+            ("local", 0x01, 1),
+            ("global", 0x11, 1),
+            ("weak", 0x21, 2),
+            ("undefined", 0x10, 0),
+        ]);
+        assert_eq!(symbols(&data), [b"global".to_vec(), b"weak".to_vec()]);
+        assert!(symbols(&data[..100]).is_empty());
+    }
+
+    #[test]
+    fn gnu() {
+        for format in [ArchiveFormat::Gnu, ArchiveFormat::Coff] {
+            let mut archive = Vec::new();
+            write_gnu(&mut archive, &members(), format).unwrap();
+            let (members, symbols) = parse_gnu(&archive);
+            let names: Vec<_> = members.iter().map(|(name, _, _)| &name[..]).collect();
+            assert_eq!(names, ["a.o", "0123456789abcdef-long.o"]);
+            assert_eq!(members[1].2, b"defg");
+            assert_eq!(
+                symbols,
+                [
+                    ("foo".to_owned(), members[0].1),
+                    ("bar".to_owned(), members[0].1),
+                    ("baz".to_owned(), members[1].1)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn darwin() {
+        let mut archive = Vec::new();
+        write_darwin(&mut archive, &members()).unwrap();
+        assert!(archive.starts_with(MAGIC));
+        assert_eq!(archive.len() % 8, 0);
+
+        let member = |pos: usize| {
+            let header = &archive[pos..pos + HEADER_LEN];
+            let name_len: usize = std::str::from_utf8(&header[3..16])
+                .unwrap()
+                .trim_end()
+                .parse()
+                .unwrap();
+            let size: usize = std::str::from_utf8(&header[48..58])
+                .unwrap()
+                .trim_end()
+                .parse()
+                .unwrap();
+            let name = &archive[pos + HEADER_LEN..pos + HEADER_LEN + name_len];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name_len)];
+            let data = pos + HEADER_LEN + name_len;
+            assert_eq!(data % 8, 0);
+            (name.to_vec(), data, pos + HEADER_LEN + size)
+        };
+        let (name, symdef, next) = member(MAGIC.len());
+        assert_eq!(name, b"__.SYMDEF");
+        let u32_at =
+            |pos: usize| u32::from_le_bytes(archive[pos..pos + 4].try_into().unwrap()) as usize;
+        assert_eq!(u32_at(symdef), 24);
+        let strings = symdef + 4 + 24 + 4;
+        let (a, a_data, b) = member(next);
+        assert_eq!(
+            (&a[..], &archive[a_data..a_data + 3]),
+            (&b"a.o"[..], &b"abc"[..])
+        );
+        assert_eq!(member(b).0, b"0123456789abcdef-long.o");
+        for (i, (symbol, member)) in [("foo", next), ("bar", next), ("baz", b)]
+            .iter()
+            .enumerate()
+        {
+            let entry = symdef + 4 + 8 * i;
+            let name = &archive[strings + u32_at(entry)..];
+            assert_eq!(
+                &name[..symbol.len() + 1],
+                format!("{}\0", symbol).as_bytes()
+            );
+            assert_eq!(u32_at(entry + 4), *member);
+        }
+    }
+
+    #[test]
+    fn read_written() {
+        for format in [
+            ArchiveFormat::Gnu,
+            ArchiveFormat::Coff,
+            ArchiveFormat::Darwin,
+        ] {
+            let mut archive = Vec::new();
+            match format {
+                ArchiveFormat::Darwin => write_darwin(&mut archive, &members()).unwrap(),
+                _ => write_gnu(&mut archive, &members(), format).unwrap(),
+            }
+            let read = read(&archive).unwrap();
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[0].0, b"a.o");
+            assert_eq!(read[1].0, b"0123456789abcdef-long.o");
+            // Darwin pads the data of the members.
+            assert!(read[0].1.starts_with(b"abc"));
+            assert!(read[1].1.starts_with(b"defg"));
+        }
+
+        // The tables of the MSVC linker are left out as well.
+        let mut archive = Vec::new();
+        write_gnu(&mut archive, &members(), ArchiveFormat::Coff).unwrap();
+        for table in ["/<ECSYMBOLS>/", "/<XFGHASHMAP>/"] {
+            header(&mut archive, table.as_bytes(), SYMBOL_TABLE, 4).unwrap();
+            archive.extend_from_slice(&[0; 4]);
+        }
+        assert_eq!(read(&archive).unwrap().len(), 2);
+
+        assert!(read(b"!<thin>\n").is_err());
+        assert!(read(b"not an archive").is_err());
+    }
+
+    #[test]
+    fn extract_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = MAGIC.to_vec();
+        for (name, data) in [("C:\\src\\a.o", "1"), ("a.o", "2"), ("A.o", "3")] {
+            header(&mut archive, b"#1/12", MEMBER, 12 + data.len()).unwrap();
+            archive.extend_from_slice(format!("{:\0<12}{}", name, data).as_bytes());
+            pad(&mut archive, 2);
+        }
+        let src = dir.path().join("lib.a");
+        fs::write(&src, archive).unwrap();
+
+        // An object of the library is already called `a.o`.
+        let mut taken = HashSet::new();
+        taken.insert("a.o".to_owned());
+        let members = extract(&src, &dir.path().join("out"), &mut taken).unwrap();
+        let names: Vec<_> = members.iter().map(|m| m.file_name().unwrap()).collect();
+        assert_eq!(names, ["a-1.o", "a-2.o", "A-3.o"]);
+        assert_eq!(fs::read(&members[2]).unwrap(), b"3");
+        let members = extract(&src, &dir.path().join("out"), &mut taken).unwrap();
+        assert_eq!(members[0].file_name().unwrap(), "a-4.o");
+    }
+}
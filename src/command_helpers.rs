@@ -2,21 +2,22 @@
 
 use std::{
     borrow::Cow,
-    collections::hash_map,
+    collections::{hash_map, HashMap},
+    env,
     ffi::OsString,
     fmt::Display,
     fs,
     hash::Hasher,
     io::{self, Read, Write},
-    path::Path,
-    process::{Child, ChildStderr, Command, Stdio},
+    path::{Component, Path, PathBuf},
+    process::{Child, ChildStderr, Command, ExitStatus, Output, Stdio},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
 };
 
-use crate::{Error, ErrorKind, Object};
+use crate::{CommandRunner, Error, ErrorKind, Object};
 
 #[derive(Clone, Debug)]
 pub(crate) struct CargoOutput {
@@ -94,6 +95,10 @@ impl CargoOutput {
 
 pub(crate) struct StderrForwarder {
     inner: Option<(ChildStderr, Vec<u8>)>,
+    /// Everything read so far, if stderr was piped.
+    captured: Option<Vec<u8>>,
+    /// Whether to forward lines as cargo warnings as they are read.
+    forward: bool,
     #[cfg(feature = "parallel")]
     is_non_blocking: bool,
     #[cfg(feature = "parallel")]
@@ -105,12 +110,15 @@ pub(crate) struct StderrForwarder {
 const MIN_BUFFER_CAPACITY: usize = 100;
 
 impl StderrForwarder {
-    pub(crate) fn new(child: &mut Child) -> Self {
+    pub(crate) fn new(child: &mut Child, forward: bool) -> Self {
+        let inner = child
+            .stderr
+            .take()
+            .map(|stderr| (stderr, Vec::with_capacity(MIN_BUFFER_CAPACITY)));
         Self {
-            inner: child
-                .stderr
-                .take()
-                .map(|stderr| (stderr, Vec::with_capacity(MIN_BUFFER_CAPACITY))),
+            captured: inner.as_ref().map(|_| Vec::new()),
+            forward,
+            inner,
             bytes_buffered: 0,
             #[cfg(feature = "parallel")]
             is_non_blocking: false,
@@ -143,7 +151,7 @@ impl StderrForwarder {
                             // On Windows, if we get an error then the pipe is broken, so flush
                             // the buffer and bail.
                             if !buffer.is_empty() {
-                                write_warning(&buffer[..]);
+                                write_warning(&buffer[..], &mut self.captured, self.forward);
                             }
                             self.inner = None;
                             break true;
@@ -183,7 +191,7 @@ impl StderrForwarder {
                             // Only forward complete lines, leave the rest in the buffer.
                             if let Some((b'\n', line)) = line.split_last() {
                                 consumed += line.len() + 1;
-                                write_warning(line);
+                                write_warning(line, &mut self.captured, self.forward);
                             }
                         }
                         if consumed > 0 && consumed < self.bytes_buffered {
@@ -195,11 +203,17 @@ impl StderrForwarder {
                     res => {
                         // End of stream: flush remaining data and bail.
                         if self.bytes_buffered > 0 {
-                            write_warning(&buffer[..self.bytes_buffered]);
+                            write_warning(
+                                &buffer[..self.bytes_buffered],
+                                &mut self.captured,
+                                self.forward,
+                            );
                         }
                         if let Err(err) = res {
                             write_warning(
                                 format!("Failed to read from child stderr: {err}").as_bytes(),
+                                &mut None,
+                                true,
                             );
                         }
                         self.inner.take();
@@ -212,6 +226,11 @@ impl StderrForwarder {
         }
     }
 
+    /// Take what was read so far, or `None` if stderr wasn't piped.
+    pub(crate) fn take_captured(&mut self) -> Option<Vec<u8>> {
+        self.captured.take()
+    }
+
     #[cfg(feature = "parallel")]
     pub(crate) fn set_non_blocking(&mut self) -> Result<(), Error> {
         assert!(!self.is_non_blocking);
@@ -237,7 +256,14 @@ impl StderrForwarder {
     }
 }
 
-fn write_warning(line: &[u8]) {
+fn write_warning(line: &[u8], captured: &mut Option<Vec<u8>>, forward: bool) {
+    if let Some(captured) = captured {
+        captured.extend_from_slice(line);
+        captured.push(b'\n');
+    }
+    if !forward {
+        return;
+    }
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
     stdout.write_all(b"cargo:warning=").unwrap();
@@ -250,89 +276,101 @@ fn wait_on_child(
     program: &Path,
     child: &mut Child,
     cargo_output: &CargoOutput,
-) -> Result<(), Error> {
-    StderrForwarder::new(child).forward_all();
+    forward: bool,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut stderr_forwarder = StderrForwarder::new(child, forward);
+    stderr_forwarder.forward_all();
 
     let status = match child.wait() {
         Ok(s) => s,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::ToolExecError,
-                format!(
-                    "Failed to wait on spawned child process, command {:?} with args {}: {}.",
-                    cmd,
-                    program.display(),
-                    e
-                ),
-            ));
-        }
+        Err(e) => return Err(wait_error(cmd, program, e)),
     };
 
     cargo_output.print_debug(&status);
 
     if status.success() {
-        Ok(())
+        Ok(stderr_forwarder.take_captured())
     } else {
-        Err(Error::new(
-            ErrorKind::ToolExecError,
-            format!(
-                "Command {:?} with args {} did not execute successfully (status code {}).",
-                cmd,
-                program.display(),
-                status
-            ),
+        Err(status_error(
+            cmd,
+            program,
+            status,
+            stderr_forwarder.take_captured(),
         ))
     }
 }
 
+fn wait_error(cmd: &Command, program: &Path, e: io::Error) -> Error {
+    Error::new(
+        ErrorKind::ToolExecError,
+        format!(
+            "Failed to wait on spawned child process, command {:?} with args {}: {}.",
+            cmd,
+            program.display(),
+            e
+        ),
+    )
+    .with_command(cmd)
+    .with_source(e)
+}
+
+fn status_error(
+    cmd: &Command,
+    program: &Path,
+    status: ExitStatus,
+    stderr: Option<Vec<u8>>,
+) -> Error {
+    Error::new(
+        ErrorKind::ToolExecError,
+        format!(
+            "Command {:?} with args {} did not execute successfully (status code {}).",
+            cmd,
+            program.display(),
+            status
+        ),
+    )
+    .with_command(cmd)
+    .with_status(status)
+    .with_stderr(stderr)
+}
+
 /// Find the destination object path for each file in the input source files,
 /// and store them in the output Object.
-pub(crate) fn objects_from_files(files: &[Arc<Path>], dst: &Path) -> Result<Vec<Object>, Error> {
+///
+/// With a `root`, the objects of the files in it mirror their path relative to
+/// it, see [`Build::mirror_source_tree`](crate::Build::mirror_source_tree).
+pub(crate) fn objects_from_files(
+    files: &[Arc<Path>],
+    dst: &Path,
+    root: Option<&Path>,
+) -> Result<Vec<Object>, Error> {
     let mut objects = Vec::with_capacity(files.len());
+    let mut sources = HashMap::new();
     for file in files {
-        let basename = file
-            .file_name()
-            .ok_or_else(|| {
-                Error::new(
-                    ErrorKind::InvalidArgument,
-                    "No file_name for object file path!",
-                )
-            })?
-            .to_string_lossy();
-        let dirname = file
-            .parent()
-            .ok_or_else(|| {
-                Error::new(
-                    ErrorKind::InvalidArgument,
-                    "No parent for object file path!",
-                )
-            })?
-            .to_string_lossy();
-
-        // Hash the dirname. This should prevent conflicts if we have multiple
-        // object files with the same filename in different subfolders.
-        let mut hasher = hash_map::DefaultHasher::new();
-
-        // Make the dirname relative (if possible) to avoid full system paths influencing the sha
-        // and making the output system-dependent
-        //
-        // NOTE: Here we allow using std::env::var (instead of Build::getenv) because
-        // CARGO_* variables always trigger a rebuild when changed
-        #[allow(clippy::disallowed_methods)]
-        let dirname = if let Some(root) = std::env::var_os("CARGO_MANIFEST_DIR") {
-            let root = root.to_string_lossy();
-            Cow::Borrowed(dirname.strip_prefix(&*root).unwrap_or(&dirname))
-        } else {
-            dirname
+        let obj = match root.and_then(|root| mirrored_object(file, dst, root)) {
+            Some(obj) => obj,
+            None => hashed_object(file, dst)?,
         };
 
-        hasher.write(dirname.as_bytes());
-        if let Some(extension) = file.extension() {
-            hasher.write(extension.to_string_lossy().as_bytes());
+        if root.is_some() {
+            // The same source added twice is compiled to the same object, as
+            // without mirroring, which isn't a collision.
+            let source = normalized_source(file);
+            match sources.insert(obj.clone(), (file, source.clone())) {
+                Some((other, other_source)) if other_source != source => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidArgument,
+                        format!(
+                            "{} and {} would both be compiled to {}",
+                            other.display(),
+                            file.display(),
+                            obj.display()
+                        ),
+                    ));
+                }
+                _ => {}
+            }
         }
-        let obj = dst
-            .join(format!("{:016x}-{}", hasher.finish(), basename))
-            .with_extension("o");
 
         match obj.parent() {
             Some(s) => fs::create_dir_all(s)?,
@@ -350,6 +388,90 @@ pub(crate) fn objects_from_files(files: &[Arc<Path>], dst: &Path) -> Result<Vec<
     Ok(objects)
 }
 
+/// The absolute path of `file`, without `.` components.
+fn normalized_source(file: &Path) -> PathBuf {
+    let file = match env::current_dir() {
+        Ok(cwd) => cwd.join(file),
+        Err(_) => file.to_path_buf(),
+    };
+    file.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// The object of `file` named after it, prefixed with a hash of its directory.
+fn hashed_object(file: &Path, dst: &Path) -> Result<PathBuf, Error> {
+    let basename = file
+        .file_name()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidArgument,
+                "No file_name for object file path!",
+            )
+        })?
+        .to_string_lossy();
+    let dirname = file
+        .parent()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidArgument,
+                "No parent for object file path!",
+            )
+        })?
+        .to_string_lossy();
+
+    // Hash the dirname. This should prevent conflicts if we have multiple
+    // object files with the same filename in different subfolders.
+    let mut hasher = hash_map::DefaultHasher::new();
+
+    // Make the dirname relative (if possible) to avoid full system paths influencing the sha
+    // and making the output system-dependent
+    //
+    // NOTE: Here we allow using std::env::var (instead of Build::getenv) because
+    // CARGO_* variables always trigger a rebuild when changed
+    #[allow(clippy::disallowed_methods)]
+    let dirname = if let Some(root) = std::env::var_os("CARGO_MANIFEST_DIR") {
+        let root = root.to_string_lossy();
+        Cow::Borrowed(dirname.strip_prefix(&*root).unwrap_or(&dirname))
+    } else {
+        dirname
+    };
+
+    hasher.write(dirname.as_bytes());
+    if let Some(extension) = file.extension() {
+        hasher.write(extension.to_string_lossy().as_bytes());
+    }
+    Ok(dst
+        .join(format!("{:016x}-{}", hasher.finish(), basename))
+        .with_extension("o"))
+}
+
+/// The object of `file` at its path relative to the absolute `root`, with
+/// `.o` appended, or `None` if it is not in `root`.
+///
+/// Files generated in `dst` (e.g. unity files) are relative to `dst` instead,
+/// so that their objects are next to them.
+fn mirrored_object(file: &Path, dst: &Path, root: &Path) -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let file = cwd.join(file);
+    let relative = file
+        .strip_prefix(cwd.join(dst))
+        .or_else(|_| file.strip_prefix(root))
+        .ok()?;
+    let mut normalized = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            // A `..` could point outside of `root`, and out of `dst`.
+            _ => return None,
+        }
+    }
+    let mut name = normalized.file_name()?.to_owned();
+    name.push(".o");
+    Some(dst.join(normalized.with_file_name(name)))
+}
+
 pub(crate) fn run(
     cmd: &mut Command,
     program: impl AsRef<Path>,
@@ -358,7 +480,23 @@ pub(crate) fn run(
     let program = program.as_ref();
 
     let mut child = spawn(cmd, program, cargo_output)?;
-    wait_on_child(cmd, program, &mut child, cargo_output)
+    wait_on_child(cmd, program, &mut child, cargo_output, true)?;
+    Ok(())
+}
+
+/// Run a command without forwarding its stderr, returning it instead (or
+/// `None` if it is discarded, see [`CargoOutput::warnings`]).
+///
+/// On failure, the stderr is part of the error.
+pub(crate) fn run_capture_stderr(
+    cmd: &mut Command,
+    program: impl AsRef<Path>,
+    cargo_output: &CargoOutput,
+) -> Result<Option<Vec<u8>>, Error> {
+    let program = program.as_ref();
+
+    let mut child = spawn(cmd, program, cargo_output)?;
+    wait_on_child(cmd, program, &mut child, cargo_output, false)
 }
 
 pub(crate) fn run_output(
@@ -382,7 +520,7 @@ pub(crate) fn run_output(
         .unwrap();
 
     // Don't care about this output, use the normal settings
-    wait_on_child(cmd, program, &mut child, cargo_output)?;
+    wait_on_child(cmd, program, &mut child, cargo_output, true)?;
 
     Ok(stdout)
 }
@@ -410,33 +548,88 @@ pub(crate) fn spawn(
         .stderr(cargo_output.stdio_for_warnings())
         .stdout(cargo_output.stdio_for_output())
         .spawn();
-    match child {
-        Ok(child) => Ok(child),
-        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-            let extra = if cfg!(windows) {
-                " (see https://docs.rs/cc/latest/cc/#compile-time-requirements \
+    child.map_err(|e| spawn_error(cmd.0, program, e))
+}
+
+fn spawn_error(cmd: &Command, program: &Path, e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        let extra = if cfg!(windows) {
+            " (see https://docs.rs/cc/latest/cc/#compile-time-requirements \
 for help)"
-            } else {
-                ""
-            };
-            Err(Error::new(
-                ErrorKind::ToolNotFound,
-                format!(
-                    "Failed to find tool. Is `{}` installed?{}",
-                    program.display(),
-                    extra
-                ),
-            ))
-        }
-        Err(e) => Err(Error::new(
+        } else {
+            ""
+        };
+        Error::new(
+            ErrorKind::ToolNotFound,
+            format!(
+                "Failed to find tool. Is `{}` installed?{}",
+                program.display(),
+                extra
+            ),
+        )
+        .with_command(cmd)
+    } else {
+        Error::new(
             ErrorKind::ToolExecError,
             format!(
                 "Command {:?} with args {} failed to start: {:?}",
-                cmd.0,
+                cmd,
                 program.display(),
                 e
             ),
-        )),
+        )
+        .with_command(cmd)
+        .with_source(e)
+    }
+}
+
+/// Run a command with `runner` rather than spawning it, handling its output
+/// like [`run`] if `forward_stderr` and like [`run_capture_stderr`] otherwise.
+pub(crate) fn run_with_runner(
+    cmd: &mut Command,
+    program: &Path,
+    cargo_output: &CargoOutput,
+    runner: &dyn CommandRunner,
+    forward_stderr: bool,
+) -> Result<Option<Vec<u8>>, Error> {
+    cargo_output.print_debug(&format_args!("running: {:?}", cmd));
+    let output = runner.run(cmd);
+    handle_output(cmd, program, cargo_output, output, forward_stderr)
+}
+
+/// Handle the `output` of a command run by a [`CommandRunner`], see
+/// [`run_with_runner`].
+pub(crate) fn handle_output(
+    cmd: &Command,
+    program: &Path,
+    cargo_output: &CargoOutput,
+    output: io::Result<Output>,
+    forward_stderr: bool,
+) -> Result<Option<Vec<u8>>, Error> {
+    let output = output.map_err(|e| spawn_error(cmd, program, e))?;
+
+    if let OutputKind::Forward = cargo_output.output {
+        let _ = io::stdout().write_all(&output.stdout);
+    }
+    let stderr = if cargo_output.warnings {
+        if forward_stderr {
+            for line in output.stderr.split(|&b| b == b'\n') {
+                if !line.is_empty() {
+                    write_warning(line, &mut None, true);
+                }
+            }
+        }
+        Some(output.stderr)
+    } else {
+        None
+    };
+
+    cargo_output.print_debug(&output.status);
+
+    if output.status.success() {
+        Ok(stderr)
+    } else {
+        Err(status_error(cmd, program, output.status, stderr))
     }
 }
 
@@ -481,29 +674,18 @@ pub(crate) fn try_wait_on_child(
             if status.success() {
                 Ok(Some(()))
             } else {
-                Err(Error::new(
-                    ErrorKind::ToolExecError,
-                    format!(
-                        "Command {:?} with args {} did not execute successfully (status code {}).",
-                        cmd,
-                        program.display(),
-                        status
-                    ),
+                Err(status_error(
+                    cmd,
+                    program,
+                    status,
+                    stderr_forwarder.take_captured(),
                 ))
             }
         }
         Ok(None) => Ok(None),
         Err(e) => {
             stderr_forwarder.forward_all();
-            Err(Error::new(
-                ErrorKind::ToolExecError,
-                format!(
-                    "Failed to wait on spawned child process, command {:?} with args {}: {}.",
-                    cmd,
-                    program.display(),
-                    e
-                ),
-            ))
+            Err(wait_error(cmd, program, e))
         }
     }
 }
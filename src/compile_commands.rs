@@ -0,0 +1,177 @@
+//! Writing `compile_commands.json` compilation databases, as consumed by
+//! clangd and other tooling.
+
+use std::{
+    collections::HashSet,
+    env, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{fragments, json::Value};
+
+/// The database entry for compiling `src` into `obj` with `cmd`, leaving out
+/// `skip_args` (which only matter to cc itself).
+pub(crate) fn entry(
+    cmd: &Command,
+    src: &Path,
+    obj: &Path,
+    skip_args: &[&str],
+) -> io::Result<Value> {
+    fn string(s: impl AsRef<Path>) -> Value {
+        Value::String(s.as_ref().to_string_lossy().into_owned())
+    }
+
+    let directory = env::current_dir()?;
+    let arguments = std::iter::once(cmd.get_program())
+        .chain(
+            cmd.get_args()
+                .filter(|arg| !skip_args.iter().any(|skip| arg == skip)),
+        )
+        .map(string)
+        .collect();
+    Ok(Value::Object(vec![
+        ("directory".into(), string(&directory)),
+        ("arguments".into(), Value::Array(arguments)),
+        ("file".into(), string(directory.join(src))),
+        ("output".into(), string(obj)),
+    ]))
+}
+
+/// Add `entries` to the `compile_commands.json` in `dir`, keeping the entries
+/// written by other builds.
+///
+/// Every entry is kept in its own file under `compile_commands.d`, see
+/// [`fragments::write`]. Entries are dropped once their source is deleted or
+/// the directory of their output is, such as by `cargo clean`. Entries for
+/// other outputs in the directories of `entries` are dropped once the output
+/// is gone, as only the build writing `entries` puts objects there.
+pub(crate) fn write(dir: &Path, entries: &[Value]) -> io::Result<()> {
+    let path =
+        |entry: &Value, field: &str| entry.get(field).and_then(Value::as_str).map(PathBuf::from);
+    let own_dirs: HashSet<PathBuf> = entries
+        .iter()
+        .filter_map(|entry| Some(path(entry, "output")?.parent()?.to_path_buf()))
+        .collect();
+    let is_stale = |entry: &Value| {
+        let (file, output) = match (path(entry, "file"), path(entry, "output")) {
+            (Some(file), Some(output)) => (file, output),
+            _ => return false,
+        };
+        let output_dir = output.parent().unwrap_or_else(|| Path::new(""));
+        !file.exists()
+            || !output_dir.exists()
+            || (own_dirs.contains(output_dir) && !output.exists())
+    };
+    fragments::write(
+        &dir.join("compile_commands.d"),
+        "output",
+        entries,
+        &dir.join("compile_commands.json"),
+        is_stale,
+        |entries| {
+            let mut database = String::from("[");
+            for entry in &entries {
+                if database.len() > 1 {
+                    database.push(',');
+                }
+                database.push_str("\n  ");
+                database.push_str(entry.trim());
+            }
+            database.push_str("\n]\n");
+            database
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let dir = env::temp_dir().join(format!("cc-compile-commands-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut cmd = Command::new("cc");
+        cmd.arg("-c").arg("a.c");
+        let a = entry(&cmd, Path::new("a.c"), Path::new("/out/a.o"), &[]).unwrap();
+        write(&dir, std::slice::from_ref(&a)).unwrap();
+        let mut cmd = Command::new("cc");
+        cmd.arg("-c").arg("b.c");
+        let b = entry(&cmd, Path::new("b.c"), Path::new("/out/b.o"), &[]).unwrap();
+        write(&dir, &[b.clone(), a.clone()]).unwrap();
+
+        let database = fs::read_to_string(dir.join("compile_commands.json")).unwrap();
+        let database = Value::parse(&database).unwrap();
+        let entries = database.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&a));
+        assert!(entries.contains(&b));
+        assert_eq!(
+            a.get("arguments").unwrap().as_array().unwrap(),
+            [
+                Value::String("cc".into()),
+                Value::String("-c".into()),
+                Value::String("a.c".into())
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune() {
+        let dir = env::temp_dir().join(format!("cc-compile-commands-prune-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let database = || {
+            let database = fs::read_to_string(dir.join("compile_commands.json")).unwrap();
+            Value::parse(&database)
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .to_vec()
+        };
+
+        let entry_for = |name: &str| {
+            let src = dir.join(format!("{}.c", name));
+            fs::write(&src, "").unwrap();
+            let obj = out.join(format!("{}.o", name));
+            fs::write(&obj, "").unwrap();
+            entry(&Command::new("cc"), &src, &obj, &[]).unwrap()
+        };
+        let a = entry_for("a");
+        let b = entry_for("b");
+        let c = entry_for("c");
+        write(&dir, &[a.clone(), b, c.clone()]).unwrap();
+        assert_eq!(database().len(), 3);
+
+        // Fragments named by older versions are replaced by the current ones.
+        let legacy = dir.join("compile_commands.d/0123456789abcdef.json");
+        fs::write(&legacy, a.to_string()).unwrap();
+        // Entries are dropped once their source is deleted, or their object
+        // is and they aren't compiled anymore.
+        fs::remove_file(dir.join("b.c")).unwrap();
+        fs::remove_file(out.join("c.o")).unwrap();
+        write(&dir, std::slice::from_ref(&a)).unwrap();
+        assert_eq!(database(), std::slice::from_ref(&a));
+        assert!(!legacy.exists());
+        assert_eq!(
+            fs::read_dir(dir.join("compile_commands.d"))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // Entries written now are kept even if their object doesn't exist yet.
+        write(&dir, &[a, c.clone()]).unwrap();
+        assert_eq!(database().len(), 2);
+        assert!(database().contains(&c));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
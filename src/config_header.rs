@@ -0,0 +1,405 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{utilities::write_if_changed, Build, Error, ErrorKind};
+
+/// A generated configuration header, like the `config.h` written by autoconf
+/// or the `configure_file` of `CMake`.
+///
+/// Defines are collected from explicit values, cargo features and the probes
+/// of a [`Build`], and [`ConfigHeader::write`] writes them to
+/// `OUT_DIR/include/<name>`. The file is only rewritten when its contents
+/// change, so that its modification time stays stable across build script
+/// runs and doesn't trigger needless recompilation.
+///
+/// # Example
+///
+/// ```no_run
+/// let mut build = cc::Build::new();
+/// cc::ConfigHeader::new("config.h")
+///     .define("VERSION", "\"1.0\"")
+///     .cargo_feature("threads", "ENABLE_THREADS")
+///     .check_header(&build, "unistd.h")?
+///     .check_function(&build, "strlcpy")?
+///     .check_sizeof(&build, "long", &[])?
+///     .write(&mut build)?;
+/// build.file("src/foo.c").compile("foo");
+/// # Ok::<(), cc::Error>(())
+/// ```
+///
+/// [`Build`]: crate::Build
+#[derive(Clone, Debug)]
+pub struct ConfigHeader {
+    name: Arc<Path>,
+    template: Option<Arc<Path>>,
+    values: Vec<(Arc<str>, Value)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Undefined,
+    Defined(Option<Arc<str>>),
+    /// Defined to 1 if the cargo feature is enabled, resolved when writing.
+    Feature(Arc<str>),
+}
+
+impl ConfigHeader {
+    /// Construct a new header written to `OUT_DIR/include/<name>`.
+    ///
+    /// `name` is a relative path, which may contain directories such as
+    /// `foo/config.h`.
+    pub fn new(name: impl AsRef<Path>) -> ConfigHeader {
+        ConfigHeader {
+            name: name.as_ref().into(),
+            template: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Fill the CMake-style template at `path` instead of listing the defines.
+    ///
+    /// In the template, `#cmakedefine VAR [value]` becomes `#define VAR
+    /// [value]` if `VAR` is defined to a true value for `CMake`, and
+    /// `/* #undef VAR */` otherwise. `#cmakedefine01 VAR` becomes `#define VAR
+    /// 1` or `#define VAR 0`. `@VAR@` and `${VAR}` are replaced with the value
+    /// of `VAR`, or nothing if it isn't defined.
+    pub fn template(&mut self, path: impl AsRef<Path>) -> &mut ConfigHeader {
+        self.template = Some(path.as_ref().into());
+        self
+    }
+
+    /// Define `name`, optionally to `value`.
+    ///
+    /// Defining a name again replaces its value.
+    pub fn define<'a, V: Into<Option<&'a str>>>(
+        &mut self,
+        name: &str,
+        value: V,
+    ) -> &mut ConfigHeader {
+        self.set(name, Value::Defined(value.into().map(Arc::from)))
+    }
+
+    /// Leave `name` undefined, written as `/* #undef name */`.
+    pub fn undef(&mut self, name: &str) -> &mut ConfigHeader {
+        self.set(name, Value::Undefined)
+    }
+
+    /// Define `name` to 1 if `condition` holds, and leave it undefined
+    /// otherwise.
+    pub fn define_if(&mut self, name: &str, condition: bool) -> &mut ConfigHeader {
+        if condition {
+            self.define(name, "1")
+        } else {
+            self.undef(name)
+        }
+    }
+
+    /// Define `name` to 1 if the cargo feature `feature` of the package being
+    /// built is enabled, and leave it undefined otherwise.
+    pub fn cargo_feature(&mut self, feature: &str, name: &str) -> &mut ConfigHeader {
+        self.set(name, Value::Feature(feature.into()))
+    }
+
+    /// Define `HAVE_<HEADER>` to 1 if `header` can be included, see
+    /// [`Build::has_header`].
+    ///
+    /// The name is formed as by autoconf, so `sys/stat.h` defines
+    /// `HAVE_SYS_STAT_H`.
+    pub fn check_header(&mut self, build: &Build, header: &str) -> Result<&mut Self, Error> {
+        let found = build.has_header(header)?;
+        Ok(self.define_if(&format!("HAVE_{}", identifier(header)), found))
+    }
+
+    /// Define `HAVE_<FUNCTION>` to 1 if `function` can be linked, see
+    /// [`Build::has_function`].
+    pub fn check_function(&mut self, build: &Build, function: &str) -> Result<&mut Self, Error> {
+        let found = build.has_function(function)?;
+        Ok(self.define_if(&format!("HAVE_{}", identifier(function)), found))
+    }
+
+    /// Define `SIZEOF_<TY>` to the size of `ty` when `headers` are included,
+    /// or to 0 if the type doesn't exist, see [`Build::sizeof_type`].
+    ///
+    /// The name is formed as by autoconf, so `unsigned long` defines
+    /// `SIZEOF_UNSIGNED_LONG` and `void *` defines `SIZEOF_VOID_P`.
+    pub fn check_sizeof(
+        &mut self,
+        build: &Build,
+        ty: &str,
+        headers: &[&str],
+    ) -> Result<&mut Self, Error> {
+        let size = build.sizeof_type(ty, headers)?.unwrap_or(0);
+        let name = format!("SIZEOF_{}", identifier(&ty.replace('*', "p")));
+        Ok(self.define(&name, &*size.to_string()))
+    }
+
+    /// Write the header to `OUT_DIR/include/<name>` and add that directory to
+    /// the include path of `build`, returning the path of the header.
+    ///
+    /// The file is left untouched if it already has the same contents.
+    pub fn write(&self, build: &mut Build) -> Result<PathBuf, Error> {
+        if self.name.as_os_str().is_empty()
+            || !self
+                .name
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                format!(
+                    "Config header name must be a relative path without `..`, got {}",
+                    self.name.display()
+                ),
+            ));
+        }
+
+        let feature_enabled = |feature: &str| {
+            let var = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+            build.getenv(&var).is_some()
+        };
+        let contents = match &self.template {
+            Some(template) => {
+                let text = fs::read_to_string(template).map_err(|e| {
+                    Error::new(
+                        ErrorKind::IOError,
+                        format!(
+                            "Failed to read config header template {}",
+                            template.display()
+                        ),
+                    )
+                    .with_source(e)
+                })?;
+                if build.emit_rerun_if_changed {
+                    build.cargo_output.print_metadata(&format_args!(
+                        "cargo:rerun-if-changed={}",
+                        template.display()
+                    ));
+                }
+                self.fill_template(&text, feature_enabled)
+            }
+            None => self.render(feature_enabled),
+        };
+
+        let dir = build.get_out_dir()?.join("include");
+        let path = dir.join(&self.name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_if_changed(&path, contents.as_bytes()).map_err(|e| {
+            Error::new(
+                ErrorKind::IOError,
+                format!("Failed to write config header {}", path.display()),
+            )
+            .with_source(e)
+        })?;
+        if !build.include_directories.iter().any(|d| **d == *dir) {
+            build.include(&dir);
+        }
+        Ok(path)
+    }
+
+    fn set(&mut self, name: &str, value: Value) -> &mut ConfigHeader {
+        match self.values.iter_mut().find(|(n, _)| &**n == name) {
+            Some((_, v)) => *v = value,
+            None => self.values.push((name.into(), value)),
+        }
+        self
+    }
+
+    /// The value of `name`, or `None` if it is undefined.
+    fn lookup(&self, name: &str, feature_enabled: &dyn Fn(&str) -> bool) -> Option<Arc<str>> {
+        let (_, value) = self.values.iter().find(|(n, _)| &**n == name)?;
+        match value {
+            Value::Undefined => None,
+            Value::Defined(value) => Some(value.clone().unwrap_or_else(|| "".into())),
+            Value::Feature(feature) if feature_enabled(feature) => Some("1".into()),
+            Value::Feature(_) => None,
+        }
+    }
+
+    fn render(&self, feature_enabled: impl Fn(&str) -> bool) -> String {
+        let mut out = String::from("/* Generated by the cc crate, do not edit. */\n");
+        for (name, _) in &self.values {
+            match self.lookup(name, &feature_enabled) {
+                Some(value) if value.is_empty() => out += &format!("#define {}\n", name),
+                Some(value) => out += &format!("#define {} {}\n", name, value),
+                None => out += &format!("/* #undef {} */\n", name),
+            }
+        }
+        out
+    }
+
+    fn fill_template(&self, template: &str, feature_enabled: impl Fn(&str) -> bool) -> String {
+        let lookup = |name: &str| self.lookup(name, &feature_enabled);
+        let mut out = String::with_capacity(template.len());
+        for line in template.split_inclusive('\n') {
+            let (line, newline) = match line.strip_suffix('\n') {
+                Some(line) => (line, "\n"),
+                None => (line, ""),
+            };
+            let line = substitute(line, &lookup);
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+            let mut words = match trimmed.strip_prefix('#') {
+                Some(rest) => rest.split_whitespace(),
+                None => "".split_whitespace(),
+            };
+            match words.next() {
+                Some("cmakedefine01") => {
+                    let name = words.next().unwrap_or_default();
+                    let value = if is_true(lookup(name).as_deref()) {
+                        1
+                    } else {
+                        0
+                    };
+                    out += &format!("{}#define {} {}", indent, name, value);
+                }
+                Some("cmakedefine") => {
+                    let name = words.next().unwrap_or_default();
+                    let value = words.collect::<Vec<_>>().join(" ");
+                    if !is_true(lookup(name).as_deref()) {
+                        out += &format!("{}/* #undef {} */", indent, name);
+                    } else if value.is_empty() {
+                        out += &format!("{}#define {}", indent, name);
+                    } else {
+                        out += &format!("{}#define {} {}", indent, name, value);
+                    }
+                }
+                _ => out += &line,
+            }
+            out += newline;
+        }
+        out
+    }
+}
+
+/// Replace `@VAR@` and `${VAR}` in `line`, leaving anything that isn't a
+/// variable reference alone.
+fn substitute(line: &str, lookup: &dyn Fn(&str) -> Option<Arc<str>>) -> String {
+    let is_name = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.' || b == b'-')
+    };
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find(['@', '$']) {
+        out += &rest[..i];
+        let (name, len) = if rest[i..].starts_with('@') {
+            match rest[i + 1..].find('@') {
+                Some(end) => (&rest[i + 1..i + 1 + end], end + 2),
+                None => ("", 0),
+            }
+        } else if rest[i..].starts_with("${") {
+            match rest[i + 2..].find('}') {
+                Some(end) => (&rest[i + 2..i + 2 + end], end + 3),
+                None => ("", 0),
+            }
+        } else {
+            ("", 0)
+        };
+        if is_name(name) {
+            out += lookup(name).as_deref().unwrap_or("");
+            rest = &rest[i + len..];
+        } else {
+            out += &rest[i..i + 1];
+            rest = &rest[i + 1..];
+        }
+    }
+    out + rest
+}
+
+/// Whether `CMake` considers `value` true in `#cmakedefine`.
+fn is_true(value: Option<&str>) -> bool {
+    let value = match value {
+        Some(value) => value,
+        None => return false,
+    };
+    let upper = value.to_ascii_uppercase();
+    !matches!(
+        &*upper,
+        "" | "0" | "OFF" | "NO" | "FALSE" | "N" | "IGNORE" | "NOTFOUND"
+    ) && !upper.ends_with("-NOTFOUND")
+}
+
+/// The autoconf identifier for `s`, such as `SYS_STAT_H` for `sys/stat.h`.
+fn identifier(s: &str) -> String {
+    s.trim()
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let mut header = ConfigHeader::new("config.h");
+        header
+            .define("VERSION", "\"1.0\"")
+            .define("EMPTY", None)
+            .undef("VERSION")
+            .define_if("HAVE_FOO", true)
+            .define_if("HAVE_BAR", false)
+            .cargo_feature("a-b", "WITH_AB")
+            .cargo_feature("c", "WITH_C")
+            .define("VERSION", "2");
+        assert_eq!(
+            header.render(|feature| feature == "a-b"),
+            "/* Generated by the cc crate, do not edit. */\n\
+             #define VERSION 2\n\
+             #define EMPTY\n\
+             #define HAVE_FOO 1\n\
+             /* #undef HAVE_BAR */\n\
+             #define WITH_AB 1\n\
+             /* #undef WITH_C */\n"
+        );
+    }
+
+    #[test]
+    fn template() {
+        let mut header = ConfigHeader::new("config.h");
+        header
+            .define("ON", "1")
+            .define("OFF_VALUE", "OFF")
+            .define("LIB", "foo-NOTFOUND")
+            .define("VERSION", "1.2")
+            .define("NAME", "cc");
+        let template = "#cmakedefine ON\n\
+                        #cmakedefine ON value @NAME@\n\
+                        \x20 #  cmakedefine OFF_VALUE\n\
+                        #cmakedefine LIB\n\
+                        #cmakedefine MISSING 1\n\
+                        #cmakedefine01 ON\n\
+                        #cmakedefine01 MISSING\n\
+                        #define VERSION \"@VERSION@ ${NAME} @MISSING@\"\n\
+                        /* user@example.com costs $5 */";
+        assert_eq!(
+            header.fill_template(template, |_| false),
+            "#define ON\n\
+             #define ON value cc\n\
+             \x20 /* #undef OFF_VALUE */\n\
+             /* #undef LIB */\n\
+             /* #undef MISSING */\n\
+             #define ON 1\n\
+             #define MISSING 0\n\
+             #define VERSION \"1.2 cc \"\n\
+             /* user@example.com costs $5 */"
+        );
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(identifier("sys/stat.h"), "SYS_STAT_H");
+        assert_eq!(identifier("unsigned long"), "UNSIGNED_LONG");
+        assert_eq!(identifier(&"void *".replace('*', "p")), "VOID_P");
+    }
+}
@@ -0,0 +1,301 @@
+//! Dependency files emitted by the compiler, and the up-to-date checks based
+//! on them.
+
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+use crate::{json, tool::ToolFamily, Tool};
+
+/// The format of the dependency information written by a compiler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DepfileFormat {
+    /// A Makefile rule, written by GCC/Clang via `-MMD -MF`.
+    Makefile,
+    /// A JSON document, written by MSVC via `/sourceDependencies`.
+    MsvcJson,
+}
+
+impl DepfileFormat {
+    pub(crate) fn for_tool(tool: &Tool) -> Self {
+        match tool.family {
+            ToolFamily::Msvc { clang_cl: false } => DepfileFormat::MsvcJson,
+            ToolFamily::Msvc { clang_cl: true } | ToolFamily::Gnu | ToolFamily::Clang { .. } => {
+                DepfileFormat::Makefile
+            }
+        }
+    }
+
+    /// The path of the depfile for the object file `obj`.
+    pub(crate) fn path(self, obj: &Path) -> PathBuf {
+        match self {
+            DepfileFormat::Makefile => obj.with_extension("d"),
+            DepfileFormat::MsvcJson => obj.with_extension("json"),
+        }
+    }
+
+    /// Add the flags asking the compiler to write the depfile for `obj`.
+    ///
+    /// `-MMD` is used rather than `-MD` so that system headers are left out.
+    pub(crate) fn add_args(self, cmd: &mut Command, tool: &Tool, obj: &Path) {
+        let path = self.path(obj);
+        match self {
+            DepfileFormat::Makefile if tool.is_like_clang_cl() => {
+                let mut mf = OsString::from("-clang:-MF");
+                mf.push(&path);
+                cmd.arg("-clang:-MMD").arg(mf);
+            }
+            DepfileFormat::Makefile => {
+                cmd.arg("-MMD").arg("-MF").arg(path);
+            }
+            DepfileFormat::MsvcJson => {
+                cmd.arg("-sourceDependencies").arg(path);
+            }
+        }
+    }
+
+    /// Read the dependencies recorded for `obj`, including its source file.
+    pub(crate) fn read(self, obj: &Path) -> io::Result<Vec<PathBuf>> {
+        let contents = fs::read_to_string(self.path(obj))?;
+        let deps = match self {
+            DepfileFormat::Makefile => Some(parse_makefile(&contents)),
+            DepfileFormat::MsvcJson => parse_msvc_json(&contents),
+        };
+        deps.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed depfile"))
+    }
+}
+
+/// Parse the prerequisites out of the Makefile rules written by `-MMD`.
+fn parse_makefile(contents: &str) -> Vec<PathBuf> {
+    let mut deps = Vec::new();
+    // The tokens of the current logical line, i.e. with escaped newlines
+    // joined.
+    let mut line = Vec::new();
+    let mut token = String::new();
+    let mut chars = contents.chars().peekable();
+
+    loop {
+        let c = chars.next();
+        match c {
+            Some('\\') => match chars.peek() {
+                Some(&(' ' | '#' | '\\')) => token.push(chars.next().unwrap()),
+                Some('\n') => {
+                    chars.next();
+                    line.extend(take_token(&mut token));
+                }
+                Some('\r') => {
+                    chars.next();
+                    chars.next_if_eq(&'\n');
+                    line.extend(take_token(&mut token));
+                }
+                _ => token.push('\\'),
+            },
+            Some('$') if chars.peek() == Some(&'$') => {
+                chars.next();
+                token.push('$');
+            }
+            Some(' ' | '\t' | '\r') => line.extend(take_token(&mut token)),
+            Some('\n') | None => {
+                line.extend(take_token(&mut token));
+                // Everything up to the token ending in `:` names the targets
+                // of the rule, the rest are its prerequisites.
+                let targets = line.iter().position(|t: &String| t.ends_with(':'));
+                let start = targets.map_or(0, |i| i + 1);
+                deps.extend(line.drain(..).skip(start).map(PathBuf::from));
+            }
+            Some(c) => token.push(c),
+        }
+        if c.is_none() {
+            return deps;
+        }
+    }
+}
+
+fn take_token(token: &mut String) -> Option<String> {
+    if token.is_empty() {
+        None
+    } else {
+        Some(std::mem::take(token))
+    }
+}
+
+/// Parse the source and includes out of the JSON written by
+/// `/sourceDependencies`.
+fn parse_msvc_json(contents: &str) -> Option<Vec<PathBuf>> {
+    let value = json::Value::parse(contents)?;
+    let data = value.get("Data")?;
+    let mut deps = vec![PathBuf::from(data.get("Source")?.as_str()?)];
+    for include in data.get("Includes")?.as_array()? {
+        deps.push(PathBuf::from(include.as_str()?));
+    }
+    Some(deps)
+}
+
+/// The directories holding system headers for `tool`, i.e. its sysroot and,
+/// for MSVC, the directories listed in `INCLUDE`.
+///
+/// `include` is the value of `INCLUDE` from the environment, used if the tool
+/// doesn't set it itself.
+pub(crate) fn system_include_dirs(tool: &Tool, include: Option<&OsStr>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    let mut args = tool.args().iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if let Some(sysroot) = arg.strip_prefix("--sysroot=") {
+            dirs.push(PathBuf::from(sysroot));
+        } else if arg == "--sysroot" || arg == "-isysroot" {
+            dirs.extend(args.next().map(PathBuf::from));
+        }
+    }
+
+    if tool.is_like_msvc() {
+        let include = tool
+            .env()
+            .iter()
+            .find(|(key, _)| key == "INCLUDE")
+            .map(|(_, value)| value.as_os_str())
+            .or(include);
+        if let Some(include) = include {
+            dirs.extend(env::split_paths(include).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+    }
+
+    dirs
+}
+
+/// Whether `path` is a header from one of the `system_dirs`.
+pub(crate) fn is_system_header(
+    path: &Path,
+    system_dirs: &[PathBuf],
+    format: DepfileFormat,
+) -> bool {
+    match format {
+        DepfileFormat::Makefile => system_dirs.iter().any(|dir| path.starts_with(dir)),
+        // MSVC writes lowercased paths, and the file system is case
+        // insensitive anyhow.
+        DepfileFormat::MsvcJson => {
+            let path = path.to_string_lossy().to_lowercase();
+            let path = Path::new(&path);
+            system_dirs
+                .iter()
+                .any(|dir| path.starts_with(dir.to_string_lossy().to_lowercase()))
+        }
+    }
+}
+
+/// The file recording the command line that produced an object file.
+fn stamp_path(obj: &Path) -> PathBuf {
+    obj.with_extension("cmdline")
+}
+
+/// Whether `obj` was produced by `cmdline` and is newer than its source,
+/// every dependency recorded for it and `extra_deps`.
+pub(crate) fn is_up_to_date(
+    src: &Path,
+    obj: &Path,
+    cmdline: &str,
+    format: DepfileFormat,
+    extra_deps: &[&Path],
+) -> bool {
+    fn mtime(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    let obj_mtime = match mtime(obj) {
+        Some(mtime) => mtime,
+        None => return false,
+    };
+    match fs::read_to_string(stamp_path(obj)) {
+        Ok(stamp) if stamp == cmdline => {}
+        _ => return false,
+    }
+    let deps = match format.read(obj) {
+        Ok(deps) => deps,
+        Err(_) => return false,
+    };
+
+    std::iter::once(src)
+        .chain(deps.iter().map(PathBuf::as_path))
+        .chain(extra_deps.iter().copied())
+        .all(|dep| matches!(mtime(dep), Some(dep_mtime) if dep_mtime <= obj_mtime))
+}
+
+/// Forget the command line of `obj`, so that it is considered out of date
+/// until [`write_stamp`] is called after a successful compilation.
+pub(crate) fn remove_stamp(obj: &Path) {
+    let _ = fs::remove_file(stamp_path(obj));
+}
+
+pub(crate) fn write_stamp(obj: &Path, cmdline: &str) -> io::Result<()> {
+    fs::write(stamp_path(obj), cmdline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makefile() {
+        let deps = parse_makefile(
+            "/out/abc-foo.o /out/abc-foo.d: src/foo.c \\\n src/foo\\ bar.h C:/x/y.h \\\r\n  a$$b.h\n\nsrc/foo.h:\n",
+        );
+        assert_eq!(
+            deps,
+            [
+                PathBuf::from("src/foo.c"),
+                PathBuf::from("src/foo bar.h"),
+                PathBuf::from("C:/x/y.h"),
+                PathBuf::from("a$b.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn msvc_json() {
+        let deps = parse_msvc_json(
+            r#"{"Version":"1.2","Data":{"Source":"c:\\src\\foo.c","ProvidedModule":"","Includes":["c:\\src\\foo.h"],"ImportedModules":[],"ImportedHeaderUnits":[]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deps,
+            [
+                PathBuf::from(r"c:\src\foo.c"),
+                PathBuf::from(r"c:\src\foo.h")
+            ]
+        );
+    }
+
+    #[test]
+    fn system_headers() {
+        let dirs = [PathBuf::from("/opt/sysroot")];
+        let format = DepfileFormat::Makefile;
+        assert!(is_system_header(
+            Path::new("/opt/sysroot/usr/include/stdio.h"),
+            &dirs,
+            format
+        ));
+        assert!(!is_system_header(
+            Path::new("/opt/sysroot2/stdio.h"),
+            &dirs,
+            format
+        ));
+        assert!(!is_system_header(Path::new("src/foo.h"), &dirs, format));
+
+        #[cfg(windows)]
+        {
+            let dirs = [PathBuf::from(r"C:\Program Files\MSVC\include")];
+            let format = DepfileFormat::MsvcJson;
+            assert!(is_system_header(
+                Path::new(r"c:\program files\msvc\include\stdio.h"),
+                &dirs,
+                format
+            ));
+        }
+    }
+}
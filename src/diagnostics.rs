@@ -0,0 +1,629 @@
+//! Diagnostics printed by compilers, either in one of their machine-readable
+//! formats or as classic `file:line:col: warning: message` text.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::json::Value;
+
+/// The severity of a [`Diagnostic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Severity {
+    /// An error, failing the compilation.
+    Error,
+    /// A warning.
+    Warning,
+    /// A note, giving more context about another diagnostic.
+    Note,
+}
+
+impl Severity {
+    fn from_name(name: &str) -> Option<Severity> {
+        match name {
+            "error" | "fatal error" => Some(Severity::Error),
+            "warning" => Some(Severity::Warning),
+            "note" | "remark" => Some(Severity::Note),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// A diagnostic printed by the compiler, see [`Build::diagnostics`].
+///
+/// Its [`Display`](fmt::Display) implementation prints it on one line, in the
+/// classic `file:line:col: severity: message` format, followed by its notes.
+///
+/// [`Build::diagnostics`]: crate::Build::diagnostics
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    file: Option<PathBuf>,
+    line: Option<u32>,
+    column: Option<u32>,
+    notes: Vec<Diagnostic>,
+    rendered: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            notes: Vec::new(),
+            rendered: None,
+        }
+    }
+
+    /// The severity of the diagnostic.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The message of the diagnostic, without its location.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The file the diagnostic is about, as printed by the compiler.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The line in [`Self::file`], starting at 1.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The column in [`Self::line`], starting at 1.
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+
+    /// The notes attached to the diagnostic, such as where a conflicting
+    /// declaration is.
+    pub fn notes(&self) -> &[Diagnostic] {
+        &self.notes
+    }
+
+    /// The diagnostic as the compiler printed it, if it printed it as text.
+    ///
+    /// This includes the lines around the diagnostic itself and its notes,
+    /// such as the files including the one it is about, and the source
+    /// snippets.
+    pub fn rendered(&self) -> Option<&str> {
+        self.rendered.as_deref()
+    }
+
+    fn push_line(&mut self, line: &str) {
+        let rendered = self.rendered.get_or_insert_with(String::new);
+        if !rendered.is_empty() {
+            rendered.push('\n');
+        }
+        rendered.push_str(line);
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+                if let Some(column) = self.column {
+                    write!(f, ":{}", column)?;
+                }
+            }
+            f.write_str(": ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)?;
+        for note in &self.notes {
+            write!(f, "; {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// The format the compiler is asked to print its diagnostics in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DiagnosticsFormat {
+    /// The classic text format, which is the default.
+    Text,
+    /// JSON, supported by GCC 9 to 14.
+    GccJson,
+    /// SARIF, supported by GCC 13 and later.
+    GccSarif,
+    /// SARIF, supported by Clang 15 and later.
+    ClangSarif,
+}
+
+impl DiagnosticsFormat {
+    /// The flags asking the compiler for this format.
+    pub(crate) fn args(self) -> &'static [&'static str] {
+        match self {
+            DiagnosticsFormat::Text => &[],
+            DiagnosticsFormat::GccJson => &["-fdiagnostics-format=json"],
+            DiagnosticsFormat::GccSarif => &["-fdiagnostics-format=sarif-stderr"],
+            DiagnosticsFormat::ClangSarif => {
+                &["-fdiagnostics-format=sarif", "-Wno-sarif-format-unstable"]
+            }
+        }
+    }
+}
+
+/// Split what a compiler printed to stderr into diagnostics and the lines
+/// that aren't part of any.
+///
+/// JSON and SARIF documents are recognized wherever they start a line, and
+/// everything else is parsed as text in the GCC/Clang or MSVC format. The
+/// indented lines following a diagnostic, such as the source snippet, are
+/// part of it, as are the lines before it which aren't part of another one,
+/// such as `In file included from ...`.
+pub(crate) fn parse(stderr: &str) -> (Vec<Diagnostic>, Vec<&str>) {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut other = Vec::new();
+    // Lines such as `In file included from ...`, giving context to the next
+    // diagnostic.
+    let mut context = Vec::new();
+    let mut in_diagnostic = false;
+
+    let mut rest = stderr;
+    while !rest.is_empty() {
+        if rest.starts_with('[') || rest.starts_with('{') {
+            let document =
+                Value::parse_prefix(rest).and_then(|(value, len)| Some((from_json(&value)?, len)));
+            if let Some((parsed, len)) = document {
+                diagnostics.extend(parsed);
+                other.append(&mut context);
+                in_diagnostic = false;
+                rest = rest[len..].trim_start_matches([' ', '\t', '\r']);
+                rest = rest.strip_prefix('\n').unwrap_or(rest);
+                continue;
+            }
+        }
+
+        let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+        rest = next;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match parse_header(line) {
+            Some(note) if note.severity == Severity::Note && in_diagnostic => {
+                let last = diagnostics.last_mut().unwrap();
+                last.push_line(line);
+                last.notes.push(note);
+            }
+            Some(mut diagnostic) => {
+                for line in context.drain(..) {
+                    diagnostic.push_line(line);
+                }
+                diagnostic.push_line(line);
+                diagnostics.push(diagnostic);
+                in_diagnostic = true;
+            }
+            None if in_diagnostic && line.starts_with([' ', '\t']) => {
+                diagnostics.last_mut().unwrap().push_line(line);
+            }
+            None => {
+                in_diagnostic = false;
+                context.push(line);
+            }
+        }
+    }
+
+    other.append(&mut context);
+    (diagnostics, other)
+}
+
+fn parse_header(line: &str) -> Option<Diagnostic> {
+    // The MSVC format is stricter, so that GCC/Clang messages are never
+    // mistaken for it.
+    parse_msvc_header(line).or_else(|| parse_gnu_header(line))
+}
+
+const SEVERITIES: [&str; 5] = ["fatal error", "error", "warning", "note", "remark"];
+
+/// Parse `file:line:col: severity: message`, where the location may be
+/// partial, the name of the program, or absent.
+fn parse_gnu_header(line: &str) -> Option<Diagnostic> {
+    let (location, severity, message) = SEVERITIES
+        .iter()
+        .filter_map(|severity| {
+            if let Some(message) = line
+                .strip_prefix(severity)
+                .and_then(|rest| rest.strip_prefix(": "))
+            {
+                return Some(("", *severity, message));
+            }
+            let separator = format!(": {}: ", severity);
+            let (location, message) = line.split_once(&separator)?;
+            Some((location, *severity, message))
+        })
+        .min_by_key(|(location, _, _)| location.len())?;
+
+    let mut diagnostic = Diagnostic::new(Severity::from_name(severity)?, message);
+    if let Some((rest, last)) = location.rsplit_once(':') {
+        if let Ok(last) = last.parse() {
+            match rest.rsplit_once(':') {
+                Some((file, line)) if line.parse::<u32>().is_ok() => {
+                    diagnostic.file = Some(PathBuf::from(file));
+                    diagnostic.line = line.parse().ok();
+                    diagnostic.column = Some(last);
+                }
+                _ => {
+                    diagnostic.file = Some(PathBuf::from(rest));
+                    diagnostic.line = Some(last);
+                }
+            }
+        }
+    }
+    Some(diagnostic)
+}
+
+/// Parse `file(line,col): severity: message` as printed by clang-cl, or
+/// `file(line): severity CODE: message` as printed by MSVC.
+fn parse_msvc_header(line: &str) -> Option<Diagnostic> {
+    let (location, rest) = line.split_once("): ")?;
+    let (file, position) = location.rsplit_once('(')?;
+    let (line, column) = match position.split_once(',') {
+        Some((line, column)) => (line.parse().ok()?, Some(column.parse().ok()?)),
+        None => (position.parse().ok()?, None),
+    };
+
+    let (severity, message) = SEVERITIES.iter().find_map(|severity| {
+        let rest = rest.strip_prefix(severity)?;
+        if let Some(message) = rest.strip_prefix(": ") {
+            return Some((*severity, message.to_owned()));
+        }
+        let (code, message) = rest.strip_prefix(' ')?.split_once(": ")?;
+        if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        Some((*severity, format!("{}: {}", code, message)))
+    })?;
+
+    let mut diagnostic = Diagnostic::new(Severity::from_name(severity)?, message);
+    diagnostic.file = Some(PathBuf::from(file));
+    diagnostic.line = Some(line);
+    diagnostic.column = column;
+    Some(diagnostic)
+}
+
+/// The diagnostics in a JSON document printed by GCC, or in a SARIF log.
+fn from_json(value: &Value) -> Option<Vec<Diagnostic>> {
+    match value {
+        Value::Array(values) => values.iter().map(from_gcc_json).collect(),
+        Value::Object(_) => from_sarif(value),
+        _ => None,
+    }
+}
+
+fn number(value: Option<&Value>) -> Option<u32> {
+    value.and_then(Value::as_f64).map(|n| n as u32)
+}
+
+fn from_gcc_json(value: &Value) -> Option<Diagnostic> {
+    let severity = match value.get("kind")?.as_str()? {
+        kind if kind.starts_with("warning") => Severity::Warning,
+        "note" => Severity::Note,
+        _ => Severity::Error,
+    };
+    let mut diagnostic = Diagnostic::new(severity, value.get("message")?.as_str()?);
+
+    let caret = value
+        .get("locations")
+        .and_then(Value::as_array)
+        .and_then(<[Value]>::first)
+        .and_then(|location| location.get("caret"));
+    if let Some(caret) = caret {
+        diagnostic.file = caret.get("file").and_then(Value::as_str).map(PathBuf::from);
+        diagnostic.line = number(caret.get("line"));
+        diagnostic.column = number(caret.get("column"));
+    }
+
+    if let Some(children) = value.get("children") {
+        for child in children.as_array()? {
+            diagnostic.notes.push(from_gcc_json(child)?);
+        }
+    }
+    Some(diagnostic)
+}
+
+fn from_sarif(value: &Value) -> Option<Vec<Diagnostic>> {
+    fn message(value: &Value) -> Option<&str> {
+        value.get("message")?.get("text")?.as_str()
+    }
+
+    fn located(mut diagnostic: Diagnostic, location: &Value, artifacts: &[Value]) -> Diagnostic {
+        let physical = match location.get("physicalLocation") {
+            Some(physical) => physical,
+            None => return diagnostic,
+        };
+        let uri = physical.get("artifactLocation").and_then(|artifact| {
+            artifact.get("uri").and_then(Value::as_str).or_else(|| {
+                let index = artifact.get("index")?.as_f64()? as usize;
+                artifacts.get(index)?.get("location")?.get("uri")?.as_str()
+            })
+        });
+        diagnostic.file = uri.map(path_from_uri);
+        if let Some(region) = physical.get("region") {
+            diagnostic.line = number(region.get("startLine"));
+            diagnostic.column = number(region.get("startColumn"));
+        }
+        diagnostic
+    }
+
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for run in value.get("runs")?.as_array()? {
+        let artifacts = run
+            .get("artifacts")
+            .and_then(Value::as_array)
+            .unwrap_or(&[]);
+        let results = run.get("results").and_then(Value::as_array).unwrap_or(&[]);
+        for result in results {
+            // The level defaults to warning.
+            let severity = match result.get("level").and_then(Value::as_str) {
+                Some("error") => Severity::Error,
+                Some("note") | Some("none") => Severity::Note,
+                _ => Severity::Warning,
+            };
+            let mut diagnostic = Diagnostic::new(severity, message(result)?);
+            if let Some(location) = result
+                .get("locations")
+                .and_then(Value::as_array)
+                .and_then(<[Value]>::first)
+            {
+                diagnostic = located(diagnostic, location, artifacts);
+            }
+            let related = result
+                .get("relatedLocations")
+                .and_then(Value::as_array)
+                .unwrap_or(&[]);
+            for location in related {
+                if let Some(message) = message(location) {
+                    let note = Diagnostic::new(Severity::Note, message);
+                    diagnostic.notes.push(located(note, location, artifacts));
+                }
+            }
+
+            match diagnostics.last_mut() {
+                Some(last) if severity == Severity::Note => last.notes.push(diagnostic),
+                _ => diagnostics.push(diagnostic),
+            }
+        }
+    }
+    Some(diagnostics)
+}
+
+/// The path of a `file://` URI, or the URI itself if it is a relative
+/// reference.
+fn path_from_uri(uri: &str) -> PathBuf {
+    let path = match uri.strip_prefix("file://") {
+        // `file:///C:/foo.c` on Windows.
+        Some(path) if path.as_bytes().get(2) == Some(&b':') => &path[1..],
+        Some(path) => path,
+        None => uri,
+    };
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) if b == b'%' => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(
+        severity: Severity,
+        message: &str,
+        file: &str,
+        line: u32,
+        column: Option<u32>,
+    ) -> Diagnostic {
+        Diagnostic {
+            file: Some(PathBuf::from(file)),
+            line: Some(line),
+            column,
+            ..Diagnostic::new(severity, message)
+        }
+    }
+
+    #[test]
+    fn text() {
+        let (mut diagnostics, other) = parse(
+            "In file included from src/foo.c:1:\n\
+             src/foo.h: In function 'f':\n\
+             src/foo.h:3:9: warning: unused variable 'x' [-Wunused-variable]\n\
+             \x20   3 |     int x;\n\
+             \x20     |         ^\n\
+             C:\\src\\foo.c:10: error: expected ';'\n\
+             src/foo.c:2:5: note: previous declaration is here\n\
+             \x20   x = 1\n\
+             clang: error: no such file or directory: 'bar.c'\n\
+             src\\foo.c(4,2): warning: unused function 'g' [-Wunused-function]\n\
+             src\\foo.c(7): fatal error C1083: Cannot open include file: 'bar.h'\n\
+             cc1: some warnings being treated as errors\n",
+        );
+
+        let rendered: Vec<_> = diagnostics
+            .iter_mut()
+            .map(|diagnostic| diagnostic.rendered.take().unwrap())
+            .collect();
+        assert_eq!(
+            rendered[0],
+            "In file included from src/foo.c:1:\n\
+             src/foo.h: In function 'f':\n\
+             src/foo.h:3:9: warning: unused variable 'x' [-Wunused-variable]\n\
+             \x20   3 |     int x;\n\
+             \x20     |         ^"
+        );
+        assert_eq!(
+            rendered[1],
+            "C:\\src\\foo.c:10: error: expected ';'\n\
+             src/foo.c:2:5: note: previous declaration is here\n\
+             \x20   x = 1"
+        );
+        assert_eq!(
+            rendered[2],
+            "clang: error: no such file or directory: 'bar.c'"
+        );
+
+        let mut error = diagnostic(Severity::Error, "expected ';'", "C:\\src\\foo.c", 10, None);
+        error.notes.push(diagnostic(
+            Severity::Note,
+            "previous declaration is here",
+            "src/foo.c",
+            2,
+            Some(5),
+        ));
+        assert_eq!(
+            diagnostics,
+            [
+                diagnostic(
+                    Severity::Warning,
+                    "unused variable 'x' [-Wunused-variable]",
+                    "src/foo.h",
+                    3,
+                    Some(9)
+                ),
+                error,
+                Diagnostic::new(Severity::Error, "no such file or directory: 'bar.c'"),
+                diagnostic(
+                    Severity::Warning,
+                    "unused function 'g' [-Wunused-function]",
+                    "src\\foo.c",
+                    4,
+                    Some(2)
+                ),
+                diagnostic(
+                    Severity::Error,
+                    "C1083: Cannot open include file: 'bar.h'",
+                    "src\\foo.c",
+                    7,
+                    None
+                ),
+            ]
+        );
+        assert_eq!(other, ["cc1: some warnings being treated as errors"]);
+
+        let (diagnostics, other) = parse("ld: cannot find -lfoo\n");
+        assert_eq!(diagnostics, []);
+        assert_eq!(other, ["ld: cannot find -lfoo"]);
+    }
+
+    #[test]
+    fn gcc_json() {
+        let (diagnostics, other) = parse(
+            r#"[{"kind": "warning", "locations": [{"caret": {"line": 3, "file": "foo.c", "column": 9}}], "children": [{"kind": "note", "locations": [], "message": "declared here"}], "message": "unused variable 'x'"}]
+cc1: all warnings being treated as errors
+"#,
+        );
+        let mut warning = diagnostic(
+            Severity::Warning,
+            "unused variable 'x'",
+            "foo.c",
+            3,
+            Some(9),
+        );
+        warning
+            .notes
+            .push(Diagnostic::new(Severity::Note, "declared here"));
+        assert_eq!(diagnostics, [warning]);
+        assert_eq!(other, ["cc1: all warnings being treated as errors"]);
+        assert_eq!(parse("[]\n"), (vec![], vec![]));
+    }
+
+    #[test]
+    fn sarif() {
+        let (diagnostics, other) = parse(
+            r#"{
+  "$schema": "https://docs.oasis-open.org/sarif/sarif/v2.1.0/cos02/schemas/sarif-schema-2.1.0.json",
+  "runs": [
+    {
+      "artifacts": [{"location": {"uri": "file:///src/my%20foo.c"}}],
+      "results": [
+        {
+          "level": "error",
+          "locations": [{"physicalLocation": {"artifactLocation": {"index": 0}, "region": {"startColumn": 3, "startLine": 4}}}],
+          "message": {"text": "use of undeclared identifier 'y'"}
+        },
+        {
+          "level": "note",
+          "locations": [{"physicalLocation": {"artifactLocation": {"uri": "file:///C:/src/foo.h"}, "region": {"startLine": 1}}}],
+          "message": {"text": "declared here"}
+        }
+      ],
+      "version": "2.1.0"
+    }
+  ]
+}
+1 error generated.
+"#,
+        );
+        let mut error = diagnostic(
+            Severity::Error,
+            "use of undeclared identifier 'y'",
+            "/src/my foo.c",
+            4,
+            Some(3),
+        );
+        error.notes.push(Diagnostic {
+            line: Some(1),
+            file: Some(PathBuf::from("C:/src/foo.h")),
+            ..Diagnostic::new(Severity::Note, "declared here")
+        });
+        assert_eq!(diagnostics, [error]);
+        assert_eq!(other, ["1 error generated."]);
+    }
+
+    #[test]
+    fn display() {
+        let mut error = diagnostic(Severity::Error, "expected ';'", "foo.c", 10, Some(2));
+        error.notes.push(diagnostic(
+            Severity::Note,
+            "declared here",
+            "foo.h",
+            2,
+            None,
+        ));
+        assert_eq!(
+            error.to_string(),
+            "foo.c:10:2: error: expected ';'; foo.h:2: note: declared here"
+        );
+        assert_eq!(
+            Diagnostic::new(Severity::Warning, "foo").to_string(),
+            "warning: foo"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{ffi::OsStr, path::Path, sync::Arc};
+
+/// The language of a source file, see [`FileOptions::language`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Language {
+    /// C, compiled by the C compiler (`CC`) with `CFLAGS`.
+    C,
+    /// C++, compiled by the C++ compiler (`CXX`) with `CXXFLAGS`.
+    Cpp,
+}
+
+impl Language {
+    /// The language of a file, from its extension as understood by GCC.
+    pub(crate) fn from_path(path: &Path) -> Option<Language> {
+        match path.extension()?.to_str()? {
+            "c" => Some(Language::C),
+            "cc" | "cp" | "cxx" | "cpp" | "CPP" | "c++" | "C" | "mm" => Some(Language::Cpp),
+            _ => None,
+        }
+    }
+
+    /// The value of `-x` to compile a header in this language.
+    pub(crate) fn header_kind(self) -> &'static str {
+        match self {
+            Language::C => "c-header",
+            Language::Cpp => "c++-header",
+        }
+    }
+}
+
+/// Options applying to a single file added with [`Build::file_with`].
+///
+/// These are merged on top of the options of the [`Build`] when compiling that
+/// file: flags and defines are passed after the build-wide ones, and the
+/// optimization level replaces the build-wide one.
+///
+/// # Example
+///
+/// ```no_run
+/// use cc::FileOptions;
+///
+/// cc::Build::new()
+///     .file("src/foo.c")
+///     .file_with(
+///         "src/slow.c",
+///         FileOptions::new()
+///             .opt_level(0)
+///             .flag("-Wno-unused-parameter")
+///             .define("SLOW", None),
+///     )
+///     .compile("foo");
+/// ```
+///
+/// [`Build`]: crate::Build
+/// [`Build::file_with`]: crate::Build::file_with
+#[derive(Clone, Debug, Default)]
+pub struct FileOptions {
+    pub(crate) flags: Vec<Arc<OsStr>>,
+    pub(crate) definitions: Vec<(Arc<str>, Option<Arc<str>>)>,
+    pub(crate) opt_level: Option<Arc<str>>,
+    pub(crate) language: Option<Language>,
+    pub(crate) unity: Option<bool>,
+}
+
+impl FileOptions {
+    /// Construct a new set of options, which doesn't change anything from the
+    /// build-wide ones.
+    pub fn new() -> FileOptions {
+        FileOptions::default()
+    }
+
+    /// Add an arbitrary flag to the invocation of the compiler for this file.
+    pub fn flag(&mut self, flag: impl AsRef<OsStr>) -> &mut FileOptions {
+        self.flags.push(flag.as_ref().into());
+        self
+    }
+
+    /// Specify a `-D` variable with an optional value for this file.
+    pub fn define<'a, V: Into<Option<&'a str>>>(&mut self, var: &str, val: V) -> &mut FileOptions {
+        self.definitions
+            .push((var.into(), val.into().map(Into::into)));
+        self
+    }
+
+    /// Compile this file as `language`, regardless of its extension.
+    ///
+    /// By default, files with a C++ extension (such as `.cpp` or `.cc`) are
+    /// compiled as C++ and every other file in the language of the build, see
+    /// [`Build::cpp`](crate::Build::cpp).
+    pub fn language(&mut self, language: Language) -> &mut FileOptions {
+        self.language = Some(language);
+        self
+    }
+
+    /// Configure whether this file may be grouped with others in a unity
+    /// build, see [`Build::unity_build`](crate::Build::unity_build).
+    ///
+    /// Files with any other option set are never grouped. This option
+    /// defaults to `true`.
+    pub fn unity(&mut self, unity: bool) -> &mut FileOptions {
+        self.unity = Some(unity);
+        self
+    }
+
+    /// Configures the optimization level of this file, see
+    /// [`Build::opt_level`](crate::Build::opt_level).
+    pub fn opt_level(&mut self, opt_level: u32) -> &mut FileOptions {
+        self.opt_level = Some(opt_level.to_string().into());
+        self
+    }
+
+    /// Configures the optimization level of this file, see
+    /// [`Build::opt_level_str`](crate::Build::opt_level_str).
+    pub fn opt_level_str(&mut self, opt_level: &str) -> &mut FileOptions {
+        self.opt_level = Some(opt_level.into());
+        self
+    }
+
+    /// Whether the file can be compiled as part of a unity file, i.e. with
+    /// the build-wide options.
+    pub(crate) fn allows_unity(&self) -> bool {
+        self.unity.unwrap_or(true) && !self.changes_compilation()
+    }
+
+    /// Whether these options change how the file is compiled, rather than
+    /// only how it is grouped with others.
+    pub(crate) fn changes_compilation(&self) -> bool {
+        !self.flags.is_empty()
+            || !self.definitions.is_empty()
+            || self.opt_level.is_some()
+            || self.language.is_some()
+    }
+}
@@ -1,7 +1,7 @@
 use crate::target::TargetInfo;
 use crate::{Build, Error, ErrorKind, Tool, ToolFamily};
 use std::borrow::Cow;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 
 #[derive(Debug, PartialEq, Default)]
 pub(crate) struct RustcCodegenFlags<'a> {
@@ -13,11 +13,13 @@ pub(crate) struct RustcCodegenFlags<'a> {
     profile_use: Option<&'a str>,
     control_flow_guard: Option<&'a str>,
     lto: Option<&'a str>,
+    linker_plugin_lto: bool,
     relocation_model: Option<&'a str>,
     embed_bitcode: Option<bool>,
     force_frame_pointers: Option<bool>,
     no_redzone: Option<bool>,
     soft_float: Option<bool>,
+    remap_path_prefix: Vec<(&'a str, &'a str)>,
 }
 
 impl<'this> RustcCodegenFlags<'this> {
@@ -39,6 +41,7 @@ impl<'this> RustcCodegenFlags<'this> {
                 "--deny",
                 "-F",
                 "--forbid",
+                "--remap-path-prefix",
             ]
             .contains(&flag)
         }
@@ -69,6 +72,24 @@ impl<'this> RustcCodegenFlags<'this> {
                 continue;
             }
 
+            // https://doc.rust-lang.org/rustc/command-line-arguments.html#--remap-path-prefix-remap-source-names-in-output
+            let remap = match prev {
+                "--remap-path-prefix" => Some(curr),
+                _ => curr.strip_prefix("--remap-path-prefix="),
+            };
+            if let Some(remap) = remap {
+                // Like rustc, split at the last `=`, as the prefix to remap
+                // is more likely to contain one.
+                let (from, to) = remap.rsplit_once('=').ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidFlag,
+                        "--remap-path-prefix must be of the form FROM=TO",
+                    )
+                })?;
+                codegen_flags.remap_path_prefix.push((from, to));
+                continue;
+            }
+
             let (prefix, rustc_flag) = handle_flag_prefix(prev, curr);
             codegen_flags.set_rustc_flag(prefix, rustc_flag)?;
         }
@@ -126,6 +147,11 @@ impl<'this> RustcCodegenFlags<'this> {
             "-Ccontrol-flow-guard" => self.control_flow_guard = value.or(Some("true")),
             // https://doc.rust-lang.org/rustc/codegen-options/index.html#lto
             "-Clto" => self.lto = value.or(Some("true")),
+            // https://doc.rust-lang.org/rustc/codegen-options/index.html#linker-plugin-lto
+            // The value is either a boolean or the path to the linker plugin.
+            "-Clinker-plugin-lto" => {
+                self.linker_plugin_lto = value.map_or(true, |v| arg_to_bool(v) != Some(false))
+            }
             // https://doc.rust-lang.org/rustc/codegen-options/index.html#relocation-model
             "-Crelocation-model" => {
                 self.relocation_model =
@@ -153,6 +179,19 @@ impl<'this> RustcCodegenFlags<'this> {
         Ok(())
     }
 
+    /// Whether rustc emits LLVM bitcode for the linker plugin to optimize,
+    /// i.e. whether the objects built by cc should take part in cross-language
+    /// LTO.
+    pub(crate) fn linker_plugin_lto(&self) -> bool {
+        self.linker_plugin_lto
+    }
+
+    /// The path prefixes rustc remaps in its output, in the order given, see
+    /// [`Build::remap_path_prefix`].
+    pub(crate) fn remap_path_prefix(&self) -> &[(&'this str, &'this str)] {
+        &self.remap_path_prefix
+    }
+
     // Rust and clang/cc don't agree on what equivalent flags should look like.
     pub(crate) fn cc_flags(&self, build: &Build, tool: &mut Tool, target: &TargetInfo<'_>) {
         let family = tool.family;
@@ -274,7 +313,10 @@ impl<'this> RustcCodegenFlags<'this> {
                 }
 
                 // https://clang.llvm.org/docs/ClangCommandLineReference.html#cmdoption-clang-flto
-                if let Some(value) = self.lto {
+                // https://doc.rust-lang.org/rustc/linker-plugin-lto.html
+                if self.linker_plugin_lto {
+                    push_if_supported("-flto=thin".into());
+                } else if let Some(value) = self.lto {
                     let cc_val = match value {
                         "y" | "yes" | "on" | "true" | "fat" => Some("full"),
                         "thin" => Some("thin"),
@@ -297,8 +339,26 @@ impl<'this> RustcCodegenFlags<'this> {
                     }
                 }
             }
-            ToolFamily::Gnu { .. } => {}
-            ToolFamily::Msvc { .. } => {
+            ToolFamily::Gnu { .. } => {
+                // GCC's LTO objects are not LLVM bitcode, so they can't be
+                // optimized together with the Rust code.
+                if self.linker_plugin_lto {
+                    build.cargo_output.print_warning(
+                        &"-Clinker-plugin-lto requires Clang, the C code will not take part in cross-language LTO",
+                    );
+                }
+            }
+            ToolFamily::Msvc { clang_cl } => {
+                // https://doc.rust-lang.org/rustc/linker-plugin-lto.html
+                if self.linker_plugin_lto {
+                    if clang_cl {
+                        push_if_supported("-flto=thin".into());
+                    } else {
+                        build.cargo_output.print_warning(
+                            &"-Clinker-plugin-lto requires clang-cl, the C code will not take part in cross-language LTO",
+                        );
+                    }
+                }
                 // https://learn.microsoft.com/en-us/cpp/build/reference/guard-enable-control-flow-guard
                 if let Some(value) = self.control_flow_guard {
                     let cc_val = match value {
@@ -323,6 +383,60 @@ impl<'this> RustcCodegenFlags<'this> {
     }
 }
 
+/// The flag, to be followed by `FROM=TO`, that makes `tool` replace the `FROM`
+/// prefix of the paths it writes into objects with `TO`, if it supports one.
+pub(crate) fn remap_path_prefix_flag(
+    build: &Build,
+    tool: &Tool,
+    target: &TargetInfo<'_>,
+) -> Option<&'static str> {
+    let candidates: &[&'static str] = match tool.family {
+        // https://gcc.gnu.org/onlinedocs/gcc/Overall-Options.html#index-ffile-prefix-map
+        // https://clang.llvm.org/docs/ClangCommandLineReference.html#cmdoption-clang-ffile-prefix-map
+        // `-ffile-prefix-map` also covers `__FILE__`, but is more recent than
+        // `-fdebug-prefix-map`, which only covers the debug info.
+        ToolFamily::Gnu | ToolFamily::Clang { .. } | ToolFamily::Msvc { clang_cl: true } => {
+            &["-ffile-prefix-map=", "-fdebug-prefix-map="]
+        }
+        // https://learn.microsoft.com/en-us/cpp/build/reference/pathmap
+        ToolFamily::Msvc { clang_cl: false } => &["/pathmap:"],
+    };
+    candidates.iter().copied().find(|flag| {
+        build
+            .is_flag_supported_inner(OsStr::new(&format!("{}a=b", flag)), tool, target)
+            .unwrap_or(false)
+    })
+}
+
+/// The major version of LLVM used by rustc, from the output of `rustc -vV`.
+pub(crate) fn rustc_llvm_major_version(verbose_version: &str) -> Option<u32> {
+    verbose_version
+        .lines()
+        .find_map(|line| line.strip_prefix("LLVM version: "))
+        .and_then(major_version)
+}
+
+/// The major version of LLVM used by Clang, from the output of
+/// `clang --version`.
+///
+/// Apple Clang has its own versioning, unrelated to the LLVM version, so
+/// it is not recognized.
+pub(crate) fn clang_llvm_major_version(version: &str) -> Option<u32> {
+    let line = version.lines().next()?;
+    if line.starts_with("Apple ") {
+        return None;
+    }
+    let (_, version) = line.split_once("clang version ")?;
+    major_version(version)
+}
+
+fn major_version(version: &str) -> Option<u32> {
+    let end = version
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(version.len());
+    version[..end].parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -439,6 +553,7 @@ mod tests {
             "80",
             "--remap-path-prefix",
             "foo=bar",
+            "--remap-path-prefix=/a=b=/c",
             "--json=artifact",
             // Codegen flags
             "-Car",
@@ -488,6 +603,7 @@ mod tests {
                 embed_bitcode: Some(false),
                 force_frame_pointers: Some(true),
                 lto: Some("false"),
+                linker_plugin_lto: true,
                 no_redzone: Some(true),
                 no_vectorize_loops: true,
                 no_vectorize_slp: true,
@@ -496,7 +612,41 @@ mod tests {
                 relocation_model: Some("pic"),
                 soft_float: Some(true),
                 branch_protection: Some("bti,pac-ret,leaf"),
+                remap_path_prefix: vec![("foo", "bar"), ("/a=b", "/c")],
             },
         );
     }
+
+    #[test]
+    fn remap_path_prefix_without_equals() {
+        assert!(RustcCodegenFlags::parse("--remap-path-prefix\u{1f}foo").is_err());
+    }
+
+    #[test]
+    fn llvm_versions() {
+        assert_eq!(
+            rustc_llvm_major_version(
+                "rustc 1.80.0 (051478957 2024-07-21)\nbinary: rustc\nhost: x86_64-unknown-linux-gnu\nrelease: 1.80.0\nLLVM version: 18.1.7\n"
+            ),
+            Some(18)
+        );
+        assert_eq!(rustc_llvm_major_version("rustc 1.80.0\n"), None);
+        assert_eq!(
+            clang_llvm_major_version(
+                "Ubuntu clang version 18.1.3 (1ubuntu1)\nTarget: x86_64-pc-linux-gnu\n"
+            ),
+            Some(18)
+        );
+        assert_eq!(
+            clang_llvm_major_version(
+                "clang version 19.0.0git (https://github.com/llvm/llvm-project abc)\n"
+            ),
+            Some(19)
+        );
+        assert_eq!(
+            clang_llvm_major_version("Apple clang version 15.0.0 (clang-1500.3.9.4)\n"),
+            None
+        );
+        assert_eq!(clang_llvm_major_version("gcc (GCC) 12.2.0\n"), None);
+    }
 }
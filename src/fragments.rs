@@ -0,0 +1,116 @@
+//! Files shared by every build script of a build, such as
+//! `compile_commands.json`, merged from fragments written by each build.
+
+use std::{
+    collections::{hash_map, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{json::Value, utilities::write_atomic};
+
+/// Add `entries` to the file at `dst`, keeping the entries written by other
+/// builds, and rendering all of them with `render`.
+///
+/// Every entry is kept in its own file under `fragments`, named after a hash
+/// of its `key` field so that a later entry with the same key replaces it,
+/// and those are merged into `dst`. Entries other than `entries` for which
+/// `is_stale` returns true are removed. Build scripts run concurrently, so all
+/// files are replaced atomically, and the merge is redone until no other
+/// build changed an entry in the meantime.
+pub(crate) fn write(
+    fragments: &Path,
+    key: &str,
+    entries: &[Value],
+    dst: &Path,
+    is_stale: impl Fn(&Value) -> bool,
+    render: impl Fn(Vec<String>) -> String,
+) -> io::Result<()> {
+    fs::create_dir_all(fragments)?;
+
+    let key_of = |entry: &Value| entry.get(key).and_then(Value::as_str).map(str::to_owned);
+    let mut written = HashSet::new();
+    for entry in entries {
+        let key = key_of(entry).unwrap_or_default();
+        let path = fragments.join(format!("{:016x}.json", stable_hash(key.as_bytes())));
+        write_atomic(&path, entry.to_string())?;
+        written.insert(path);
+    }
+
+    loop {
+        let merged = list_fragments(fragments)?;
+        // The newest fragment for each key, as fragments written by older
+        // versions of cc may be named differently.
+        let mut by_key: HashMap<Option<String>, (&Path, SystemTime, String)> = HashMap::new();
+        for (path, modified) in &merged {
+            // Skip entries that were removed by someone else since listing.
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let value = Value::parse(&contents);
+            if !written.contains(path) && value.as_ref().map_or(false, &is_stale) {
+                remove(path)?;
+                continue;
+            }
+            let key = value.as_ref().and_then(key_of);
+            match by_key.entry(key) {
+                hash_map::Entry::Occupied(mut entry) => {
+                    // Prefer the fragment just written, then the newest.
+                    let (old, old_modified, _) = entry.get();
+                    if (written.contains(*old), *old_modified)
+                        >= (written.contains(&**path), *modified)
+                    {
+                        remove(path)?;
+                    } else {
+                        remove(old)?;
+                        entry.insert((path, *modified, contents));
+                    }
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert((path, *modified, contents));
+                }
+            }
+        }
+        let mut contents: Vec<_> = by_key.into_values().collect();
+        contents.sort_by(|a, b| a.0.cmp(b.0));
+        write_atomic(dst, render(contents.into_iter().map(|c| c.2).collect()))?;
+
+        if list_fragments(fragments)? == merged {
+            return Ok(());
+        }
+    }
+}
+
+/// Remove the fragment at `path`, which may have been removed by someone
+/// else already.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which unlike `DefaultHasher` is the
+/// same with every Rust version, so that fragment names are too.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The entries in `dir` and their modification times, sorted by path.
+fn list_fragments(dir: &Path) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut fragments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().map_or(false, |ext| ext == "json") {
+            fragments.push((path, entry.metadata()?.modified()?));
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
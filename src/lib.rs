@@ -76,7 +76,12 @@
 //!   trailing spaces. This compiler must understand the `-c` flag. For
 //!   certain `TARGET`s, it also is assumed to know about other flags (most
 //!   common is `-fPIC`).
-//! * `AR` - the `ar` (archiver) executable to use to build the static library.
+//! * `AR` - the `ar` (archiver) executable to use to build the static library,
+//!   or `builtin` to have `cc` write it itself.
+//! * `OBJCOPY` - the `objcopy` executable used to rename and localize symbols,
+//!   see [`Build::symbol_prefix`] and [`Build::localize_symbols`].
+//! * `LDFLAGS` - a series of space separated flags passed to the linker when
+//!   linking shared libraries and executables, see [`Build::get_linker`].
 //! * `CRATE_CC_NO_DEFAULTS` - the default compiler flags may cause conflicts in
 //!   some cross compiling scenarios. Setting this variable
 //!   will disable the generation of default compiler
@@ -88,6 +93,20 @@
 //!   arguments (similar to `make` and `cmake`) rather than splitting them on each space.
 //!   For example, with `CFLAGS='a "b c"'`, the compiler will be invoked with 2 arguments -
 //!   `a` and `b c` - rather than 3: `a`, `"b` and `c"`.
+//! * `CC_COMPILE_COMMANDS_DIR` - if set, a `compile_commands.json` with an entry for
+//!   every compiled object is maintained in this directory, see [`Build::compile_commands`].
+//! * `CC_TIMINGS` - if set to the (absolute) path of a file, the start time, duration,
+//!   jobserver token wait and command of every compiled object and archiving step are
+//!   recorded in it as a JSON array. If the file name ends with `.trace.json`, a Chrome
+//!   `trace_event` file is written instead, which can be opened in `chrome://tracing` or
+//!   Perfetto, and includes the `-ftime-trace` of each object compiled by Clang. Every
+//!   build using `cc` adds its steps to the same file.
+//! * `SOURCE_DATE_EPOCH` - if set, passed to the compiler, which GCC and Clang use for
+//!   `__DATE__` and `__TIME__`, and makes archives deterministic, see
+//!   [`Build::deterministic`].
+//! * `CC_VERIFY_REPRODUCIBLE` - if set, every object is compiled a second time, and the
+//!   build fails with an [`ErrorKind::NotReproducible`] error listing the objects whose
+//!   contents differ between the two compilations.
 //! * `CXX...` - see [C++ Support](#c-support).
 //! * `CC_FORCE_DISABLE` - If set, `cc` will never run any [`Command`]s, and methods that
 //!   would return an [`Error`]. This is intended for use by third-party build systems
@@ -223,20 +242,27 @@
 #![warn(clippy::doc_markdown)]
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{hash_map, HashMap, HashSet};
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display};
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io::{self, Write};
 use std::path::{Component, Path, PathBuf};
 #[cfg(feature = "parallel")]
 use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
+#[cfg(feature = "parallel")]
+use std::process::Output;
 use std::sync::{
     atomic::{AtomicU8, Ordering::Relaxed},
     Arc, RwLock,
 };
+use std::time::Duration;
+#[cfg(feature = "parallel")]
+use std::time::Instant;
 
 use shlex::Shlex;
 
@@ -264,10 +290,44 @@ use utilities::*;
 mod flags;
 use flags::*;
 
+mod depfile;
+use depfile::DepfileFormat;
+
+mod json;
+
+mod compile_commands;
+mod fragments;
+
+mod archive;
+#[doc(hidden)]
+pub use archive::__elf_object;
+use archive::ArchiveFormat;
+
+mod timings;
+use timings::Timer;
+
+mod config_header;
+pub use config_header::ConfigHeader;
+
+mod file_options;
+pub use file_options::{FileOptions, Language};
+
+mod diagnostics;
+use diagnostics::DiagnosticsFormat;
+pub use diagnostics::{Diagnostic, Severity};
+
+mod plan;
+pub use plan::{BuildPlan, PlannedObject};
+
+mod runner;
+pub use runner::CommandRunner;
+
 #[derive(Debug, Eq, PartialEq, Hash)]
 struct CompilerFlag {
     compiler: Box<Path>,
     flag: Box<OsStr>,
+    /// Whether the flag counts as supported even if the compiler warns.
+    allow_warnings: bool,
 }
 
 type Env = Option<Arc<OsStr>>;
@@ -277,9 +337,16 @@ struct BuildCache {
     env_cache: RwLock<HashMap<Box<str>, Env>>,
     apple_sdk_root_cache: RwLock<HashMap<Box<str>, Arc<OsStr>>>,
     apple_versions_cache: RwLock<HashMap<Box<str>, Arc<str>>>,
+    /// The major version of LLVM used by a compiler or rustc, by path.
+    llvm_version_cache: RwLock<HashMap<Box<Path>, Option<u32>>>,
     cached_compiler_family: RwLock<CompilerFamilyLookupCache>,
     known_flag_support_status_cache: RwLock<HashMap<CompilerFlag, bool>>,
+    /// The results of probes, by command line.
+    probe_cache: RwLock<HashMap<Box<[Box<OsStr>]>, CheckResult>>,
     target_info_parser: target::TargetInfoParser,
+    diagnostics: RwLock<Vec<Diagnostic>>,
+    /// The steps timed for `CC_TIMINGS` and not written yet.
+    timings: RwLock<Vec<json::Value>>,
 }
 
 /// A builder for compilation of a native library.
@@ -292,12 +359,19 @@ pub struct Build {
     include_directories: Vec<Arc<Path>>,
     definitions: Vec<(Arc<str>, Option<Arc<str>>)>,
     objects: Vec<Arc<Path>>,
+    archives: Vec<Arc<Path>>,
+    symbol_prefix: Option<Arc<str>>,
+    localize_symbols: Option<Vec<Arc<str>>>,
     flags: Vec<Arc<OsStr>>,
     flags_supported: Vec<Arc<OsStr>>,
     ar_flags: Vec<Arc<OsStr>>,
     asm_flags: Vec<Arc<OsStr>>,
     no_default_flags: bool,
     files: Vec<Arc<Path>>,
+    file_options: Vec<(Arc<Path>, Arc<FileOptions>)>,
+    /// The language of the file compiled by a build derived by `file_build`,
+    /// which its extension may contradict.
+    file_language: Option<Language>,
     cpp: bool,
     cpp_link_stdlib: Option<Option<Arc<str>>>,
     cpp_set_stdlib: Option<Arc<str>>,
@@ -305,6 +379,8 @@ pub struct Build {
     cudart: Option<Arc<str>>,
     ccbin: bool,
     std: Option<Arc<str>>,
+    c_std: Option<Arc<str>>,
+    cpp_std: Option<Arc<str>>,
     target: Option<Arc<str>>,
     /// The host compiler.
     ///
@@ -319,6 +395,7 @@ pub struct Build {
     archiver: Option<Arc<Path>>,
     ranlib: Option<Arc<Path>>,
     cargo_output: CargoOutput,
+    machine_readable_diagnostics: bool,
     link_lib_modifiers: Vec<Arc<OsStr>>,
     pic: Option<bool>,
     use_plt: Option<bool>,
@@ -332,11 +409,55 @@ pub struct Build {
     shell_escaped_flags: Option<bool>,
     build_cache: Arc<BuildCache>,
     inherit_rustflags: bool,
+    incremental: bool,
+    emit_rerun_if_changed: bool,
+    soname: Option<Arc<str>>,
+    compile_commands: Option<bool>,
+    precompiled_header: Option<Arc<Path>>,
+    unity_batch_size: Option<usize>,
+    on_error: OnError,
+    runner: Option<Arc<dyn CommandRunner>>,
+    mirror_source_tree: bool,
+    source_root: Option<Arc<Path>>,
+    remap_path_prefix: Vec<(Arc<Path>, Arc<Path>)>,
+    deterministic: Option<bool>,
 }
 
-/// Represents the types of errors that may occur while using cc-rs.
+/// What to do when an object fails to compile, see [`Build::on_error`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OnError {
+    /// Stop at the first failure, killing the compilers still running.
+    #[default]
+    FailFast,
+    /// Compile every object, and report all the failures at the end.
+    KeepGoing,
+}
+
+/// The outcome of [`Build::check_compiles`] or [`Build::check_links`].
 #[derive(Clone, Debug)]
-enum ErrorKind {
+pub struct CheckResult {
+    success: bool,
+    diagnostics: Arc<[Diagnostic]>,
+}
+
+impl CheckResult {
+    /// Whether the program compiled (and linked, for [`Build::check_links`]).
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    /// The diagnostics the compiler printed for the program, such as the
+    /// errors explaining why it didn't compile.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+/// Represents the types of errors that may occur while using cc-rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
     /// Error occurred while performing I/O.
     IOError,
     /// Environment variable not found, with the var in question as extra info.
@@ -360,6 +481,9 @@ enum ErrorKind {
     JobserverHelpThreadError,
     /// `cc` has been disabled by an environment variable.
     Disabled,
+    /// An object differed when compiled a second time, see
+    /// `CC_VERIFY_REPRODUCIBLE`.
+    NotReproducible,
 }
 
 /// Represents an internal error that occurred, with an explanation.
@@ -369,6 +493,17 @@ pub struct Error {
     kind: ErrorKind,
     /// More explanation of error that occurred.
     message: Cow<'static, str>,
+    /// The program and arguments of the command that failed, if any.
+    command: Option<Vec<OsString>>,
+    /// The exit status of the command that failed, if it ran to completion.
+    status: Option<ExitStatus>,
+    /// What the command that failed printed to stderr, if it was captured.
+    stderr: Option<Vec<u8>>,
+    /// The underlying I/O error, if any.
+    source: Option<Arc<io::Error>>,
+    /// The errors of the sources that failed to compile, if there were
+    /// several.
+    failures: Vec<(PathBuf, Error)>,
 }
 
 impl Error {
@@ -376,13 +511,83 @@ impl Error {
         Error {
             kind,
             message: message.into(),
+            command: None,
+            status: None,
+            stderr: None,
+            source: None,
+            failures: Vec::new(),
         }
     }
+
+    fn with_command(mut self, cmd: &Command) -> Error {
+        self.command = Some(
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(OsString::from)
+                .collect(),
+        );
+        self
+    }
+
+    fn with_status(mut self, status: ExitStatus) -> Error {
+        self.status = Some(status);
+        self
+    }
+
+    fn with_stderr(mut self, stderr: Option<Vec<u8>>) -> Error {
+        self.stderr = stderr;
+        self
+    }
+
+    fn with_source(mut self, source: io::Error) -> Error {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// The kind of error that occurred.
+    ///
+    /// This can be used to tell, for example, a missing compiler
+    /// ([`ErrorKind::ToolNotFound`]) apart from a failed compilation
+    /// ([`ErrorKind::ToolExecError`]).
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The program and arguments of the command that failed, if the error was
+    /// caused by running a command.
+    pub fn command(&self) -> Option<&[OsString]> {
+        self.command.as_deref()
+    }
+
+    /// The exit status of the command that failed, if it ran to completion.
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    /// What the command that failed printed to stderr, such as the
+    /// diagnostics of the compiler.
+    ///
+    /// This is only captured when the output is forwarded as cargo warnings,
+    /// see [`Build::cargo_warnings`]. Note that MSVC prints its diagnostics to
+    /// stdout instead.
+    pub fn stderr(&self) -> Option<&[u8]> {
+        self.stderr.as_deref()
+    }
+
+    /// The sources that failed to compile and their errors, with their own
+    /// command, status and stderr, when several did with
+    /// [`OnError::KeepGoing`].
+    ///
+    /// This is empty for other errors, including when a single source failed
+    /// to compile, which is then described by this error itself.
+    pub fn failures(&self) -> &[(PathBuf, Error)] {
+        &self.failures
+    }
 }
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
-        Error::new(ErrorKind::IOError, format!("{}", e))
+        Error::new(ErrorKind::IOError, format!("{}", e)).with_source(e)
     }
 }
 
@@ -392,7 +597,13 @@ impl Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
 
 /// Represents an object.
 ///
@@ -410,6 +621,32 @@ impl Object {
     }
 }
 
+/// A header precompiled by [`Build::precompile_header`].
+struct PrecompiledHeader {
+    /// The language of the files the header was precompiled for.
+    language: Language,
+    /// The arguments making the compiler use the precompiled header.
+    args: Vec<OsString>,
+    /// The header and the precompiled header produced from it.
+    header: Object,
+    depfile: DepfileFormat,
+    /// The object produced alongside the precompiled header, which must be
+    /// linked in as well.
+    object: Option<Object>,
+}
+
+/// The compiler invocation producing an [`Object`].
+struct CompileCommand<'a> {
+    obj: &'a Object,
+    cmd: Command,
+    program: Cow<'static, Path>,
+    /// The format of the depfile requested from the compiler, if any.
+    depfile: Option<DepfileFormat>,
+    diagnostics: DiagnosticsFormat,
+    /// The Clang `-ftime-trace` file requested for `CC_TIMINGS`, if any.
+    time_trace: Option<PathBuf>,
+}
+
 /// Configure the builder.
 impl Build {
     /// Construct a new instance of a blank set of configuration.
@@ -422,12 +659,17 @@ impl Build {
             include_directories: Vec::new(),
             definitions: Vec::new(),
             objects: Vec::new(),
+            archives: Vec::new(),
+            symbol_prefix: None,
+            localize_symbols: None,
             flags: Vec::new(),
             flags_supported: Vec::new(),
             ar_flags: Vec::new(),
             asm_flags: Vec::new(),
             no_default_flags: false,
             files: Vec::new(),
+            file_options: Vec::new(),
+            file_language: None,
             shared_flag: None,
             static_flag: None,
             cpp: false,
@@ -437,6 +679,8 @@ impl Build {
             cudart: None,
             ccbin: true,
             std: None,
+            c_std: None,
+            cpp_std: None,
             target: None,
             host: None,
             out_dir: None,
@@ -448,6 +692,7 @@ impl Build {
             archiver: None,
             ranlib: None,
             cargo_output: CargoOutput::new(),
+            machine_readable_diagnostics: false,
             link_lib_modifiers: Vec::new(),
             pic: None,
             use_plt: None,
@@ -459,6 +704,18 @@ impl Build {
             shell_escaped_flags: None,
             build_cache: Arc::default(),
             inherit_rustflags: true,
+            incremental: false,
+            emit_rerun_if_changed: false,
+            soname: None,
+            compile_commands: None,
+            precompiled_header: None,
+            unity_batch_size: None,
+            on_error: OnError::FailFast,
+            runner: None,
+            mirror_source_tree: false,
+            source_root: None,
+            remap_path_prefix: Vec::new(),
+            deterministic: None,
         }
     }
 
@@ -546,6 +803,72 @@ impl Build {
         self
     }
 
+    /// Add the members of an existing static library (a `.a` or `.lib`
+    /// archive) to the library being built, so that it is self-contained.
+    ///
+    /// The members are extracted into the output directory and archived with
+    /// the other objects. Members whose file name clashes with that of
+    /// another object are renamed.
+    pub fn archive<P: AsRef<Path>>(&mut self, archive: P) -> &mut Build {
+        self.archives.push(archive.as_ref().into());
+        self
+    }
+
+    /// Add the members of existing static libraries to the library being
+    /// built, see [`Build::archive`].
+    pub fn archives<P>(&mut self, archives: P) -> &mut Build
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        for archive in archives {
+            self.archive(archive);
+        }
+        self
+    }
+
+    /// Add `prefix` to the names of the global symbols defined by the
+    /// library, so that it can be linked into the same binary as another
+    /// version of itself.
+    ///
+    /// The symbols are renamed in every object of the library, along with the
+    /// references to them, with `objcopy --redefine-syms` (the `OBJCOPY`
+    /// environment variable, or the `objcopy` of the target, `llvm-objcopy`
+    /// for Apple targets as GNU `objcopy` can't rewrite Mach-O). The library's
+    /// headers still declare the original names, so the Rust code calling it
+    /// must use the new ones, for example with `#[link_name]`.
+    ///
+    /// This isn't supported for MSVC targets.
+    pub fn symbol_prefix(&mut self, prefix: &str) -> &mut Build {
+        self.symbol_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Make the global symbols defined by the library local, except for those
+    /// in `except`, so that only those can be linked to and the others can't
+    /// clash with the symbols of other libraries.
+    ///
+    /// The objects of the library are linked into a single object with `-r`,
+    /// keeping the references between them, and the other symbols are then
+    /// made local with `objcopy --keep-global-symbols` (or the linker's
+    /// `-exported_symbols_list` on Apple targets). The names in `except` are
+    /// those of the sources, without any [`Build::symbol_prefix`].
+    ///
+    /// This isn't supported for MSVC targets.
+    pub fn localize_symbols<I, S>(&mut self, except: I) -> &mut Build
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.localize_symbols = Some(
+            except
+                .into_iter()
+                .map(|symbol| symbol.as_ref().into())
+                .collect(),
+        );
+        self
+    }
+
     /// Add an arbitrary flag to the invocation of the compiler
     ///
     /// # Example
@@ -680,6 +1003,27 @@ impl Build {
         self
     }
 
+    /// Set the name under which the shared library built by
+    /// [`Build::compile_shared`] gets recorded by the programs linking to it.
+    ///
+    /// This is the `SONAME` on ELF targets, and the install name on Apple
+    /// targets. It defaults to the file name of the library on ELF targets,
+    /// and to `@rpath/` followed by the file name on Apple targets. It has no
+    /// effect on Windows.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .file("src/foo.c")
+    ///     .soname("libfoo.so.1")
+    ///     .compile_shared("foo");
+    /// ```
+    pub fn soname(&mut self, soname: &str) -> &mut Build {
+        self.soname = Some(soname.into());
+        self
+    }
+
     /// Set the `-static` flag.
     ///
     /// When enabled on systems that support dynamic linking, this prevents
@@ -716,6 +1060,17 @@ impl Build {
         self
     }
 
+    /// Add a file which will be compiled with `options` on top of the
+    /// build-wide options.
+    ///
+    /// See [`FileOptions`] for an example.
+    pub fn file_with<P: AsRef<Path>>(&mut self, p: P, options: &FileOptions) -> &mut Build {
+        let p: Arc<Path> = p.as_ref().into();
+        self.files.push(p.clone());
+        self.file_options.push((p, Arc::new(options.clone())));
+        self
+    }
+
     /// Add files which will be compiled
     pub fn files<P>(&mut self, p: P) -> &mut Build
     where
@@ -735,8 +1090,13 @@ impl Build {
 
     /// Set C++ support.
     ///
+    /// When enabled, every file is compiled with the C++ compiler. Otherwise,
+    /// only files with a C++ extension (such as `.cpp` or `.cc`) are, and the
+    /// rest are compiled with the C compiler; see [`FileOptions::language`]
+    /// to override this per file. Both can be mixed in a single library.
+    ///
     /// The other `cpp_*` options will only become active if this is set to
-    /// `true`.
+    /// `true`, or if a file is compiled as C++.
     ///
     /// The name of the C++ standard library to link is decided by:
     /// 1. If [`cpp_link_stdlib`](Build::cpp_link_stdlib) is set, use its value.
@@ -823,6 +1183,32 @@ impl Build {
         self
     }
 
+    /// Specify the language standard version for the files compiled as
+    /// `language`, taking precedence over [`Build::std`].
+    ///
+    /// This is useful for builds mixing C and C++ files, as `std` only applies
+    /// to files in the language of the build.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cc::Language;
+    ///
+    /// cc::Build::new()
+    ///     .file("src/foo.c")
+    ///     .file("src/bar.cpp")
+    ///     .std_for(Language::C, "c11")
+    ///     .std_for(Language::Cpp, "c++17")
+    ///     .compile("foobar");
+    /// ```
+    pub fn std_for(&mut self, language: Language, std: &str) -> &mut Build {
+        match language {
+            Language::C => self.c_std = Some(std.into()),
+            Language::Cpp => self.cpp_std = Some(std.into()),
+        }
+        self
+    }
+
     /// Set warnings into errors flag.
     ///
     /// Disabled by default.
@@ -1049,6 +1435,51 @@ impl Build {
         self
     }
 
+    /// Replace the `from` prefix of the paths written into objects, in debug
+    /// info and `__FILE__`, with `to`, to make them independent of where they
+    /// are built.
+    ///
+    /// This is passed as `-ffile-prefix-map` to GCC and Clang (falling back to
+    /// `-fdebug-prefix-map` if unsupported), and as `/pathmap` to MSVC. The
+    /// prefixes given to rustc with `--remap-path-prefix` in `RUSTFLAGS` are
+    /// remapped too, unless [`Build::inherit_rustflags`] is disabled; the
+    /// ones given here come after them, and so take precedence.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .file("src/foo.c")
+    ///     .remap_path_prefix(env!("CARGO_MANIFEST_DIR"), "/foo")
+    ///     .compile("foo");
+    /// ```
+    pub fn remap_path_prefix<A: AsRef<Path>, B: AsRef<Path>>(
+        &mut self,
+        from: A,
+        to: B,
+    ) -> &mut Build {
+        self.remap_path_prefix
+            .push((from.as_ref().into(), to.as_ref().into()));
+        self
+    }
+
+    /// Configures whether archives are built deterministically, without
+    /// timestamps, uids or gids, so that building the same sources gives the
+    /// same library byte for byte.
+    ///
+    /// This passes the `D` modifier to GNU and LLVM `ar` (Apple's `ar` is
+    /// always told to zero dates), and `-Brepro` to `lib.exe`.
+    ///
+    /// This is enabled by default if the `SOURCE_DATE_EPOCH` environment
+    /// variable is set. Whether or not this is enabled, `SOURCE_DATE_EPOCH` is
+    /// passed to the compiler, which GCC and Clang use for `__DATE__` and
+    /// `__TIME__`. Setting `CC_VERIFY_REPRODUCIBLE` checks that the objects
+    /// are reproducible, see the [crate-level documentation](crate).
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut Build {
+        self.deterministic = Some(deterministic);
+        self
+    }
+
     /// Configures the output directory where all object files and static
     /// libraries will be located.
     ///
@@ -1059,6 +1490,43 @@ impl Build {
         self
     }
 
+    /// Configures whether object files are laid out in the output directory
+    /// like the sources they are compiled from.
+    ///
+    /// By default, objects are named after the source file, prefixed with a
+    /// hash of its directory (e.g. `0123456789abcdef-foo.o`). With this
+    /// enabled, the object of `src/foo/bar.c` is `src/foo/bar.c.o` in the
+    /// output directory instead, which is easier to map back to its source,
+    /// e.g. when reading linker map files.
+    ///
+    /// The paths are relative to the [`Build::source_root`], `CARGO_MANIFEST_DIR`
+    /// by default. Sources outside of it keep their hashed names. An error is
+    /// returned if two sources would be compiled to the same object.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .file("src/foo/bar.c")
+    ///     .mirror_source_tree(true)
+    ///     .compile("foo");
+    /// ```
+    pub fn mirror_source_tree(&mut self, mirror: bool) -> &mut Build {
+        self.mirror_source_tree = mirror;
+        self
+    }
+
+    /// Configures the directory [`Build::mirror_source_tree`] lays out object
+    /// files relative to.
+    ///
+    /// This defaults to the `CARGO_MANIFEST_DIR` environment variable, or the
+    /// current directory if it is not set. A relative path is relative to the
+    /// current directory.
+    pub fn source_root<P: AsRef<Path>>(&mut self, root: P) -> &mut Build {
+        self.source_root = Some(root.as_ref().into());
+        self
+    }
+
     /// Configures the compiler to be used to produce output.
     ///
     /// This option is automatically determined from the target platform or a
@@ -1074,6 +1542,11 @@ impl Build {
     /// This option is automatically determined from the target platform or a
     /// number of environment variables, so it's not required to call this
     /// function.
+    ///
+    /// The archiver `builtin` makes `cc` write the archive and its symbol
+    /// table itself, without running any tool. It is also used when the
+    /// archiver can't be found. It can't index LLVM bitcode, so an error is
+    /// returned instead when building for `-Clinker-plugin-lto`.
     pub fn archiver<P: AsRef<Path>>(&mut self, archiver: P) -> &mut Build {
         self.archiver = Some(archiver.as_ref().into());
         self
@@ -1115,6 +1588,20 @@ impl Build {
         self
     }
 
+    /// Configure whether the compiler is asked for machine-readable
+    /// diagnostics where it supports them, see [`Build::diagnostics`].
+    /// Defaults to `false`.
+    ///
+    /// When enabled, GCC 13 and later and Clang 15 and later print SARIF
+    /// (GCC 9 to 12 print JSON) instead of text, which is parsed more
+    /// reliably. Finding out whether the compiler supports it takes an extra
+    /// compilation, and the stderr of a failed compilation, as returned by
+    /// [`Error::stderr`], is in that format too.
+    pub fn machine_readable_diagnostics(&mut self, enable: bool) -> &mut Build {
+        self.machine_readable_diagnostics = enable;
+        self
+    }
+
     /// Define whether debug information should be emitted for cargo. Defaults to whether
     /// or not the environment variable `CC_ENABLE_DEBUG_OUTPUT` is set.
     ///
@@ -1216,12 +1703,193 @@ impl Build {
     /// Configure whether cc should automatically inherit compatible flags passed to rustc
     /// from `CARGO_ENCODED_RUSTFLAGS`.
     ///
+    /// When rustc is passed `-Clinker-plugin-lto`, this makes Clang emit LLVM
+    /// bitcode with `-flto=thin` and archive it with `llvm-ar`, so that the C
+    /// code takes part in [cross-language LTO]. A warning is emitted if the
+    /// LLVM versions of Clang and rustc differ, as the bitcode may then fail
+    /// to link.
+    ///
     /// This option defaults to `true`.
+    ///
+    /// [cross-language LTO]: https://doc.rust-lang.org/rustc/linker-plugin-lto.html
     pub fn inherit_rustflags(&mut self, inherit_rustflags: bool) -> &mut Build {
         self.inherit_rustflags = inherit_rustflags;
         self
     }
 
+    /// Configure whether objects that are already up to date should be left
+    /// alone instead of being compiled again.
+    ///
+    /// When enabled, the compiler is asked to write a depfile next to each
+    /// object file in `OUT_DIR` (`-MMD -MF` for GCC and Clang,
+    /// `/sourceDependencies` for MSVC). On later builds, an object is only
+    /// recompiled if its command line changed, or if its source file or any
+    /// of the headers it includes is newer than the object. Headers from
+    /// system directories are not tracked.
+    ///
+    /// This option defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .files(["src/foo.c", "src/bar.c"])
+    ///     .incremental(true)
+    ///     .compile("foo");
+    /// ```
+    pub fn incremental(&mut self, incremental: bool) -> &mut Build {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Configure whether `cargo:rerun-if-changed` should be emitted for every
+    /// compiled source file and every header it includes.
+    ///
+    /// The headers are collected from depfiles written by the compiler while
+    /// compiling the objects, so they are always exactly the ones used by the
+    /// build. System headers and headers under the sysroot are left out.
+    ///
+    /// Note that once a build script emits any `rerun-if-changed` line, Cargo
+    /// no longer reruns it when other files in the package change.
+    ///
+    /// This has no effect if the `cargo_metadata` option is `false`.
+    ///
+    /// This option defaults to `false`.
+    pub fn emit_rerun_if_changed(&mut self, emit_rerun_if_changed: bool) -> &mut Build {
+        self.emit_rerun_if_changed = emit_rerun_if_changed;
+        self
+    }
+
+    /// Precompile `header` once, and use it when compiling every file in the
+    /// language of the build, as if each of them started by including it.
+    ///
+    /// This speeds up builds where many files include the same large set of
+    /// headers. The header is precompiled with `-x c-header`/`-x c++-header`
+    /// for GCC and Clang and with `/Yc` for MSVC, and is only precompiled
+    /// again when its command line or any header it includes changed.
+    ///
+    /// Files added with [`FileOptions`] changing their flags, definitions,
+    /// optimization level or language don't use the precompiled header.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .cpp(true)
+    ///     .files(["src/a.cpp", "src/b.cpp"])
+    ///     .precompiled_header("src/pch.h")
+    ///     .compile("foo");
+    /// ```
+    pub fn precompiled_header<P: AsRef<Path>>(&mut self, header: P) -> &mut Build {
+        self.precompiled_header = Some(header.as_ref().into());
+        self
+    }
+
+    /// Compile the C and C++ files in groups of up to `batch_size`, by
+    /// generating unity files in `OUT_DIR` which `#include` the files of a
+    /// group, and compiling those instead.
+    ///
+    /// This reduces the number of compiler invocations, which dominate the
+    /// build time of libraries made of many small files. Files that don't
+    /// compile correctly when grouped, for example because they define
+    /// conflicting `static` functions, can be left out using
+    /// [`FileOptions::unity`]. Files added with other [`FileOptions`],
+    /// assembly and CUDA files are never grouped.
+    ///
+    /// A `batch_size` of at most 1 disables unity builds, which is the
+    /// default.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cc::FileOptions;
+    ///
+    /// cc::Build::new()
+    ///     .files(["src/a.c", "src/b.c", "src/c.c"])
+    ///     .file_with("src/conflicting.c", FileOptions::new().unity(false))
+    ///     .unity_build(16)
+    ///     .compile("foo");
+    /// ```
+    pub fn unity_build(&mut self, batch_size: usize) -> &mut Build {
+        self.unity_batch_size = Some(batch_size);
+        self
+    }
+
+    /// Configure what happens when an object fails to compile.
+    ///
+    /// With [`OnError::FailFast`], the default, compilation stops at the
+    /// first failure: no further compilers are started, and those running in
+    /// parallel are killed. With [`OnError::KeepGoing`], every object is
+    /// compiled and the returned error lists every source that failed, so
+    /// that all of them can be fixed at once, see [`Error::failures`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// cc::Build::new()
+    ///     .files(["src/a.c", "src/b.c"])
+    ///     .on_error(cc::OnError::KeepGoing)
+    ///     .compile("foo");
+    /// ```
+    pub fn on_error(&mut self, on_error: OnError) -> &mut Build {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Run the commands building the library with `runner` instead of
+    /// spawning them directly.
+    ///
+    /// This allows wrapping the commands, e.g. in a sandbox or a remote
+    /// execution service, or recording them. The output of the commands is
+    /// handled as usual, and with the `parallel` feature the runner is still
+    /// only called once a jobserver token is acquired. See [`CommandRunner`]
+    /// for which commands are run with it.
+    ///
+    /// Note that with [`OnError::FailFast`], the commands a runner is running
+    /// when another one fails can't be killed; they run to completion in the
+    /// background.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    ///
+    /// cc::Build::new()
+    ///     .file("src/foo.c")
+    ///     .runner(|cmd: &mut Command| {
+    ///         let mut wrapped = Command::new("sandbox-exec");
+    ///         wrapped.arg(cmd.get_program()).args(cmd.get_args());
+    ///         for (key, value) in cmd.get_envs() {
+    ///             match value {
+    ///                 Some(value) => wrapped.env(key, value),
+    ///                 None => wrapped.env_remove(key),
+    ///             };
+    ///         }
+    ///         wrapped.output()
+    ///     })
+    ///     .compile("foo");
+    /// ```
+    pub fn runner(&mut self, runner: impl CommandRunner + 'static) -> &mut Build {
+        self.runner = Some(Arc::new(runner));
+        self
+    }
+
+    /// Configure whether the commands used to compile each object should be
+    /// recorded in a `compile_commands.json` compilation database, for use by
+    /// clangd and other tooling.
+    ///
+    /// The database is written to the directory named by the
+    /// `CC_COMPILE_COMMANDS_DIR` environment variable if set, and otherwise
+    /// to the profile directory above `OUT_DIR` (e.g. `target/debug`). The
+    /// entries of every `Build`, across every crate using `cc`, are merged
+    /// into that one database.
+    ///
+    /// This option defaults to whether `CC_COMPILE_COMMANDS_DIR` is set.
+    pub fn compile_commands(&mut self, compile_commands: bool) -> &mut Build {
+        self.compile_commands = Some(compile_commands);
+        self
+    }
+
     #[doc(hidden)]
     pub fn __set_env<A, B>(&mut self, a: A, b: B) -> &mut Build
     where
@@ -1255,15 +1923,10 @@ impl Build {
     }
 
     fn ensure_check_file(&self) -> Result<PathBuf, Error> {
-        let out_dir = self.get_out_dir()?;
-        let src = if self.cuda {
+        if self.cuda {
             assert!(self.cpp);
-            out_dir.join("flag_check.cu")
-        } else if self.cpp {
-            out_dir.join("flag_check.cpp")
-        } else {
-            out_dir.join("flag_check.c")
-        };
+        }
+        let src = self.check_file(&self.get_out_dir()?, "flag_check");
 
         if !src.exists() {
             let mut f = fs::File::create(&src)?;
@@ -1273,24 +1936,99 @@ impl Build {
         Ok(src)
     }
 
-    fn is_flag_supported_inner(
-        &self,
-        flag: &OsStr,
-        tool: &Tool,
-        target: &TargetInfo<'_>,
-    ) -> Result<bool, Error> {
-        let compiler_flag = CompilerFlag {
-            compiler: tool.path().into(),
-            flag: flag.into(),
+    /// The test program `stem` in `dir`, with the extension of the language
+    /// of the build.
+    fn check_file(&self, dir: &Path, stem: &str) -> PathBuf {
+        let ext = if self.cuda {
+            "cu"
+        } else if self.cpp {
+            "cpp"
+        } else {
+            "c"
         };
+        dir.join(format!("{}.{}", stem, ext))
+    }
 
-        if let Some(is_supported) = self
-            .build_cache
-            .known_flag_support_status_cache
-            .read()
-            .unwrap()
-            .get(&compiler_flag)
-            .cloned()
+    /// The command compiling the test program `src` with `compiler` into the
+    /// object `dst.o`, or into the executable `dst` if `link`.
+    fn check_command(
+        &self,
+        compiler: &Tool,
+        target: &TargetInfo<'_>,
+        src: &Path,
+        dst: &Path,
+        link: bool,
+    ) -> Command {
+        let mut cmd = compiler.to_command();
+        if link && compiler.is_like_msvc() {
+            // Keep the executable (and the object) out of the current
+            // directory.
+            let mut fo = OsString::from("-Fo");
+            fo.push(dst.with_extension("o"));
+            let mut fe = OsString::from("-Fe");
+            fe.push(dst.with_extension("exe"));
+            cmd.arg(fo).arg(fe);
+        } else {
+            let output = if link {
+                dst.to_path_buf()
+            } else {
+                dst.with_extension("o")
+            };
+            command_add_output_file(
+                &mut cmd,
+                &output,
+                CmdAddOutputFileArgs {
+                    cuda: self.cuda,
+                    is_assembler_msvc: false,
+                    msvc: compiler.is_like_msvc(),
+                    clang: compiler.is_like_clang(),
+                    gnu: compiler.family == ToolFamily::Gnu,
+                    is_asm: false,
+                    is_arm: matches!(target.arch, "aarch64" | "arm"),
+                },
+            );
+        }
+        if !link {
+            cmd.arg("-c");
+        }
+        if compiler.supports_path_delimiter() {
+            cmd.arg("--");
+        }
+        cmd.arg(src);
+        cmd
+    }
+
+    fn is_flag_supported_inner(
+        &self,
+        flag: &OsStr,
+        tool: &Tool,
+        target: &TargetInfo<'_>,
+    ) -> Result<bool, Error> {
+        self.is_flag_supported_with(flag, tool, target, false)
+    }
+
+    /// Compile a test file with `flag`, which is supported if that succeeds
+    /// without any output on stderr, or with some if `allow_warnings`.
+    fn is_flag_supported_with(
+        &self,
+        flag: &OsStr,
+        tool: &Tool,
+        target: &TargetInfo<'_>,
+        allow_warnings: bool,
+    ) -> Result<bool, Error> {
+        let compiler_flag = CompilerFlag {
+            compiler: tool.path().into(),
+            flag: flag.into(),
+            allow_warnings,
+        };
+
+        if let Some(is_supported) = self
+            .build_cache
+            .known_flag_support_status_cache
+            .read()
+            .unwrap()
+            .get(&compiler_flag)
+            .cloned()
         {
             return Ok(is_supported);
         }
@@ -1330,29 +2068,7 @@ impl Build {
             compiler.push_cc_arg("-Wno-unused-command-line-argument".into());
         }
 
-        let mut cmd = compiler.to_command();
-        let is_arm = matches!(target.arch, "aarch64" | "arm");
-        let clang = compiler.is_like_clang();
-        let gnu = compiler.family == ToolFamily::Gnu;
-        command_add_output_file(
-            &mut cmd,
-            &obj,
-            CmdAddOutputFileArgs {
-                cuda: self.cuda,
-                is_assembler_msvc: false,
-                msvc: compiler.is_like_msvc(),
-                clang,
-                gnu,
-                is_asm: false,
-                is_arm,
-            },
-        );
-
-        if compiler.supports_path_delimiter() {
-            cmd.arg("--");
-        }
-
-        cmd.arg(&src);
+        let mut cmd = self.check_command(&compiler, target, &src, &obj, true);
 
         // On MSVC skip the CRT by setting the entry point to `main`.
         // This way we don't need to add the default library paths.
@@ -1362,7 +2078,7 @@ impl Build {
         }
 
         let output = cmd.output()?;
-        let is_supported = output.status.success() && output.stderr.is_empty();
+        let is_supported = output.status.success() && (allow_warnings || output.stderr.is_empty());
 
         self.build_cache
             .known_flag_support_status_cache
@@ -1373,38 +2089,271 @@ impl Build {
         Ok(is_supported)
     }
 
+    /// Check whether the header `header` can be included, like autoconf's
+    /// `AC_CHECK_HEADER`.
+    ///
+    /// This and the other probes compile a small test program with the
+    /// compiler, flags, include directories and definitions of the build, so
+    /// they work when cross-compiling. Their results are cached.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut build = cc::Build::new();
+    /// if build.has_header("unistd.h").unwrap() {
+    ///     build.define("HAVE_UNISTD_H", "1");
+    /// }
+    /// ```
+    pub fn has_header(&self, header: &str) -> Result<bool, Error> {
+        self.probe(&probe_source(&[header], "", ""), false)
+    }
+
+    /// Check whether the function `function` can be linked to, like
+    /// autoconf's `AC_CHECK_FUNC`.
+    ///
+    /// Unlike the other probes, the test program is linked, which needs the
+    /// libraries of the target. The function doesn't need to be declared by
+    /// any header, see [`Build::has_symbol`] for that.
+    pub fn has_function(&self, function: &str) -> Result<bool, Error> {
+        let decls = format!(
+            "#ifdef __cplusplus\nextern \"C\"\n#endif\nchar {}(void);\n",
+            function
+        );
+        let source = probe_source(&[], &decls, &format!("return {}();", function));
+        // Don't let the compiler complain that the declaration doesn't match
+        // that of a builtin function.
+        let compiler = self.try_get_compiler()?;
+        if compiler.is_like_msvc() || self.cuda {
+            self.probe(&source, true)
+        } else {
+            self.clone().flag("-fno-builtin").probe(&source, true)
+        }
+    }
+
+    /// Check whether `symbol` is declared as a function, variable or macro by
+    /// the headers `headers`, like autoconf's `AC_CHECK_DECL`.
+    pub fn has_symbol(&self, symbol: &str, headers: &[&str]) -> Result<bool, Error> {
+        let body = format!("#ifndef {0}\n    (void) {0};\n#endif", symbol);
+        self.probe(&probe_source(headers, "", &body), false)
+    }
+
+    /// Check whether `ty` is a (complete) type once the headers `headers`
+    /// are included, like autoconf's `AC_CHECK_TYPE`.
+    pub fn has_type(&self, ty: &str, headers: &[&str]) -> Result<bool, Error> {
+        let decls = format!("typedef {} cc_probe_type;\n", ty);
+        let body = "(void) sizeof(cc_probe_type);";
+        self.probe(&probe_source(headers, &decls, body), false)
+    }
+
+    /// The size of the type `ty` once the headers `headers` are included, or
+    /// `None` if it isn't a type, like autoconf's `AC_CHECK_SIZEOF`.
+    ///
+    /// The size is found without running anything, by compiling test
+    /// programs which only compile if it is in a given range.
+    pub fn sizeof_type(&self, ty: &str, headers: &[&str]) -> Result<Option<usize>, Error> {
+        if !self.has_type(ty, headers)? {
+            return Ok(None);
+        }
+        let expr = format!("sizeof({})", ty);
+        self.probe_value(headers, "", &expr).map(Some)
+    }
+
+    /// The alignment of the type `ty` once the headers `headers` are
+    /// included, or `None` if it isn't a type, like autoconf's
+    /// `AC_CHECK_ALIGNOF`. See [`Build::sizeof_type`].
+    pub fn alignof_type(&self, ty: &str, headers: &[&str]) -> Result<Option<usize>, Error> {
+        if !self.has_type(ty, headers)? {
+            return Ok(None);
+        }
+        let decls = format!(
+            "#include <stddef.h>\nstruct cc_probe_align {{ char c; {} t; }};\n",
+            ty
+        );
+        self.probe_value(headers, &decls, "offsetof(struct cc_probe_align, t)")
+            .map(Some)
+    }
+
+    /// Check whether the struct or union type `ty` has a member `member`
+    /// once the headers `headers` are included, like autoconf's
+    /// `AC_CHECK_MEMBER`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let build = cc::Build::new();
+    /// let nsec = build
+    ///     .has_member("struct stat", "st_mtim.tv_nsec", &["sys/stat.h"])
+    ///     .unwrap();
+    /// ```
+    pub fn has_member(&self, ty: &str, member: &str, headers: &[&str]) -> Result<bool, Error> {
+        let decls = format!("static {} cc_probe_value;\n", ty);
+        let body = format!("(void) sizeof(cc_probe_value.{});", member);
+        self.probe(&probe_source(headers, &decls, &body), false)
+    }
+
+    /// Work out the value of the integer constant expression `expr`, with
+    /// the headers `headers` and the declarations `decls`, as autoconf does:
+    /// by doubling an upper bound and then bisecting, checking each bound
+    /// with a test program which only compiles if `expr` is below it.
+    fn probe_value(&self, headers: &[&str], decls: &str, expr: &str) -> Result<usize, Error> {
+        let at_most = |bound: usize| {
+            let decls = format!(
+                "{}typedef char cc_probe_check[({}) <= {} ? 1 : -1];\n",
+                decls, expr, bound
+            );
+            self.probe(&probe_source(headers, &decls, ""), false)
+        };
+
+        let mut low = 0;
+        let mut high = 1;
+        while !at_most(high)? {
+            low = high + 1;
+            high = high.checked_mul(2).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::ToolExecError,
+                    format!("Failed to work out the value of `{}`", expr),
+                )
+            })?;
+        }
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if at_most(mid)? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Check whether the program `source`, written in `language`, compiles
+    /// with the compiler, flags, include directories and definitions of the
+    /// build.
+    ///
+    /// This is meant for what the other probes don't cover, such as whether
+    /// the compiler supports a type like `__int128`, or is affected by a bug
+    /// which needs a workaround. The program is only compiled, see
+    /// [`Build::check_links`] to link it as well. The result is cached.
+    ///
+    /// The diagnostics printed by the compiler are returned along with the
+    /// result, and also printed as debug output if the program doesn't
+    /// compile. They aren't part of [`Build::diagnostics`], which only has
+    /// those of the sources of the build.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cc::Language;
+    ///
+    /// let mut build = cc::Build::new();
+    /// let result = build.check_compiles("__int128 x;", Language::C).unwrap();
+    /// if result.success() {
+    ///     build.define("HAVE_INT128", "1");
+    /// } else {
+    ///     for diagnostic in result.diagnostics() {
+    ///         println!("cargo:warning=no __int128: {}", diagnostic);
+    ///     }
+    /// }
+    /// ```
+    pub fn check_compiles(&self, source: &str, language: Language) -> Result<CheckResult, Error> {
+        self.check(source, language, false)
+    }
+
+    /// Check whether the program `source`, written in `language`, compiles
+    /// and links into an executable, see [`Build::check_compiles`].
+    ///
+    /// The program needs a `main` function, and linking needs the libraries
+    /// of the target.
+    pub fn check_links(&self, source: &str, language: Language) -> Result<CheckResult, Error> {
+        self.check(source, language, true)
+    }
+
+    fn check(&self, source: &str, language: Language, link: bool) -> Result<CheckResult, Error> {
+        let result = self.with_language(language).probe_output(source, link)?;
+        if !result.success {
+            for diagnostic in result.diagnostics() {
+                self.cargo_output.print_debug(diagnostic);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Compile the test program `source` with the compiler, flags, include
+    /// directories and definitions of the build, linking it if `link`, and
+    /// return whether that succeeded.
+    fn probe(&self, source: &str, link: bool) -> Result<bool, Error> {
+        self.probe_output(source, link).map(|result| result.success)
+    }
+
+    /// Like [`Build::probe`], also returning the diagnostics printed by the
+    /// compiler.
+    fn probe_output(&self, source: &str, link: bool) -> Result<CheckResult, Error> {
+        let compiler = self.try_get_compiler()?;
+        let target = self.get_target()?;
+        let dir = self.get_out_dir()?.join("probes");
+
+        let mut hasher = hash_map::DefaultHasher::new();
+        source.hash(&mut hasher);
+        let name = format!("{:016x}", hasher.finish());
+        let src = self.check_file(&dir, &name);
+        let mut cmd = self.check_command(&compiler, &target, &src, &dir.join(&name), link);
+        for (a, b) in self.env.iter() {
+            cmd.env(a, b);
+        }
+
+        let key: Box<[Box<OsStr>]> = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(Into::into)
+            .collect();
+        if let Some(result) = self.build_cache.probe_cache.read().unwrap().get(&key) {
+            return Ok(result.clone());
+        }
+
+        fs::create_dir_all(&dir)?;
+        fs::write(&src, source)?;
+        let output = cmd.output()?;
+        let (mut diagnostics, _) = diagnostics::parse(&String::from_utf8_lossy(&output.stderr));
+        if compiler.is_like_msvc() {
+            // MSVC prints its diagnostics to stdout.
+            let (printed, _) = diagnostics::parse(&String::from_utf8_lossy(&output.stdout));
+            diagnostics.extend(printed);
+        }
+        let result = CheckResult {
+            success: output.status.success(),
+            diagnostics: Arc::from(diagnostics),
+        };
+        self.build_cache
+            .probe_cache
+            .write()
+            .unwrap()
+            .insert(key, result.clone());
+        Ok(result)
+    }
+
     /// Run the compiler, generating the file `output`
     ///
     /// This will return a result instead of panicking; see [`Self::compile()`] for
     /// the complete description.
     pub fn try_compile(&self, output: &str) -> Result<(), Error> {
-        let mut output_components = Path::new(output).components();
-        match (output_components.next(), output_components.next()) {
-            (Some(Component::Normal(_)), None) => {}
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidArgument,
-                    "argument of `compile` must be a single normal path component",
-                ));
-            }
-        }
+        check_output_name(output, "compile")?;
 
-        let (lib_name, gnu_lib_name) = if output.starts_with("lib") && output.ends_with(".a") {
-            (&output[3..output.len() - 2], output.to_owned())
-        } else {
-            let mut gnu = String::with_capacity(5 + output.len());
-            gnu.push_str("lib");
-            gnu.push_str(output);
-            gnu.push_str(".a");
-            (output, gnu)
-        };
+        let (lib_name, gnu_lib_name) = lib_names(output);
         let dst = self.get_out_dir()?;
 
-        let objects = objects_from_files(&self.files, &dst)?;
-
-        self.compile_objects(&objects)?;
+        let objects = self.compile_files(&dst)?;
         self.assemble(lib_name, &dst.join(gnu_lib_name), &objects)?;
 
+        for line in self.link_metadata(lib_name, &dst)? {
+            self.cargo_output.print_metadata(&line);
+        }
+
+        Ok(())
+    }
+
+    /// The cargo metadata linking in the library `lib_name` compiled into
+    /// `dst`, along with the libraries it depends on.
+    fn link_metadata(&self, lib_name: &str, dst: &Path) -> Result<Vec<String>, Error> {
+        let mut metadata = Vec::new();
         let target = self.get_target()?;
         if target.env == "msvc" {
             let compiler = self.get_base_compiler()?;
@@ -1420,41 +2369,25 @@ impl Build {
                 });
 
             if let Some(atlmfc_lib) = atlmfc_lib {
-                self.cargo_output.print_metadata(&format_args!(
+                metadata.push(format!(
                     "cargo:rustc-link-search=native={}",
                     atlmfc_lib.display()
                 ));
             }
         }
 
-        if self.link_lib_modifiers.is_empty() {
-            self.cargo_output
-                .print_metadata(&format_args!("cargo:rustc-link-lib=static={}", lib_name));
-        } else {
-            self.cargo_output.print_metadata(&format_args!(
-                "cargo:rustc-link-lib=static:{}={}",
-                JoinOsStrs {
-                    slice: &self.link_lib_modifiers,
-                    delimiter: ','
-                },
-                lib_name
-            ));
-        }
-        self.cargo_output.print_metadata(&format_args!(
-            "cargo:rustc-link-search=native={}",
-            dst.display()
-        ));
+        metadata.push(self.link_lib_metadata("static", lib_name));
+        metadata.push(format!("cargo:rustc-link-search=native={}", dst.display()));
 
         // Add specific C++ libraries, if enabled.
-        if self.cpp {
+        if self.uses_cpp() {
             if let Some(stdlib) = self.get_cpp_link_stdlib()? {
-                self.cargo_output
-                    .print_metadata(&format_args!("cargo:rustc-link-lib={}", stdlib.display()));
+                metadata.push(format!("cargo:rustc-link-lib={}", stdlib.display()));
             }
             // Link c++ lib from WASI sysroot
             if target.os == "wasi" {
                 if let Ok(wasi_sysroot) = self.wasi_sysroot() {
-                    self.cargo_output.print_metadata(&format_args!(
+                    metadata.push(format!(
                         "cargo:rustc-flags=-L {}/lib/{} -lstatic=c++ -lstatic=c++abi",
                         Path::new(&wasi_sysroot).display(),
                         self.get_raw_target()?
@@ -1496,7 +2429,7 @@ impl Build {
                     }
                 }
                 if libtst && libdir.is_dir() {
-                    self.cargo_output.print_metadata(&format_args!(
+                    metadata.push(format!(
                         "cargo:rustc-link-search=native={}",
                         libdir.to_str().unwrap()
                     ));
@@ -1508,12 +2441,11 @@ impl Build {
                     "static" => "cudart_static",
                     bad => panic!("unsupported cudart option: {}", bad),
                 };
-                self.cargo_output
-                    .print_metadata(&format_args!("cargo:rustc-link-lib={}", lib));
+                metadata.push(format!("cargo:rustc-link-lib={}", lib));
             }
         }
 
-        Ok(())
+        Ok(metadata)
     }
 
     /// Run the compiler, generating the file `output`
@@ -1559,54 +2491,770 @@ impl Build {
         }
     }
 
-    /// Run the compiler, generating intermediate files, but without linking
-    /// them into an archive file.
+    /// Work out the commands [`Build::compile`] would run to compile the
+    /// library `output`, without running them.
     ///
-    /// This will return a list of compiled object files, in the same order
-    /// as they were passed in as `file`/`files` methods.
-    pub fn compile_intermediates(&self) -> Vec<PathBuf> {
-        match self.try_compile_intermediates() {
-            Ok(v) => v,
-            Err(e) => fail(&e.message),
+    /// This is intended for build systems such as Bazel or Buck, which run
+    /// the commands themselves. The compiler, archiver and flags are found
+    /// just like when compiling, which may still run the compiler to detect
+    /// its family or check which flags it supports. The unity files of
+    /// [`Build::unity_build`] are written, and the members of the
+    /// [`Build::archive`]s merged into the library are extracted into
+    /// `OUT_DIR`, for the archive commands to add them to it. Everything
+    /// else is left to the caller: the objects are compiled regardless of
+    /// whether they are up to date, and the files `cc` would print
+    /// `cargo:rerun-if-changed` for are not known.
+    ///
+    /// Builds using [`Build::symbol_prefix`] or [`Build::localize_symbols`]
+    /// can't be planned, as they rewrite the compiled objects.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let plan = cc::Build::new().file("src/foo.c").try_plan("foo").unwrap();
+    /// for object in plan.objects() {
+    ///     println!("{:?}", object.command());
+    /// }
+    /// ```
+    pub fn try_plan(&self, output: &str) -> Result<BuildPlan, Error> {
+        check_output_name(output, "try_plan")?;
+        if self.symbol_prefix.is_some() || self.localize_symbols.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                "builds using `symbol_prefix` or `localize_symbols` can't be planned",
+            ));
         }
-    }
 
-    /// Run the compiler, generating intermediate files, but without linking
-    /// them into an archive file.
-    ///
-    /// This will return a result instead of panicking; see `compile_intermediates()` for the complete description.
-    pub fn try_compile_intermediates(&self) -> Result<Vec<PathBuf>, Error> {
+        let (lib_name, gnu_lib_name) = lib_names(output);
         let dst = self.get_out_dir()?;
-        let objects = objects_from_files(&self.files, &dst)?;
+        let lib = dst.join(gnu_lib_name);
+
+        let files = self.unity_files(&dst)?;
+        let mut objects = objects_from_files(&files, &dst, self.object_root()?.as_deref())?;
+        let mut planned = Vec::with_capacity(objects.len() + 1);
+        let pch = match self.plan_precompiled_header(&dst)? {
+            Some((pch, command)) => {
+                let header = &pch.header;
+                let object = pch.object.as_ref().unwrap_or(header);
+                planned.push(PlannedObject {
+                    source: header.src.clone(),
+                    object: object.dst.clone(),
+                    command,
+                });
+                Some(pch)
+            }
+            None => None,
+        };
+        for obj in &objects {
+            let cmd = self.create_compile_object_cmd(obj, pch.as_ref(), false)?;
+            planned.push(PlannedObject {
+                source: obj.src.clone(),
+                object: obj.dst.clone(),
+                command: cmd.cmd,
+            });
+        }
+        objects.extend(pch.and_then(|pch| pch.object));
 
-        self.compile_objects(&objects)?;
+        let mut objs: Vec<_> = objects
+            .iter()
+            .map(|o| o.dst.as_path())
+            .chain(self.objects.iter().map(std::ops::Deref::deref))
+            .collect();
+        let members = self.extract_archives(&dst, lib_name, &objs)?;
+        objs.extend(members.iter().map(PathBuf::as_path));
+        let mut archive_commands = Vec::new();
+        // The built-in archiver runs no commands.
+        if self.builtin_archive_format()?.is_none() {
+            for (i, chunk) in objs.chunks(100).enumerate() {
+                archive_commands.push(self.archive_command(&lib, chunk, i > 0)?.0);
+            }
+            if self.cuda && self.cuda_file_count() > 0 {
+                let (nvcc, dlink) = self.cuda_dlink_command(lib_name, &lib)?;
+                archive_commands.push(nvcc);
+                archive_commands.push(self.archive_command(&lib, &[dlink.as_path()], true)?.0);
+            }
+            if self.get_target()?.env != "msvc" {
+                archive_commands.push(self.ranlib_command(&lib)?.0);
+            }
+        }
 
-        Ok(objects.into_iter().map(|v| v.dst).collect())
-    }
+        let metadata = if self.cargo_output.metadata {
+            self.link_metadata(lib_name, &dst)?
+        } else {
+            Vec::new()
+        };
 
-    #[cfg(feature = "parallel")]
-    fn compile_objects(&self, objs: &[Object]) -> Result<(), Error> {
-        use std::cell::Cell;
+        Ok(BuildPlan {
+            objects: planned,
+            archive_commands,
+            output: lib,
+            metadata,
+        })
+    }
 
-        use parallel::async_executor::{block_on, YieldOnce};
+    fn link_lib_metadata(&self, kind: &str, lib_name: &str) -> String {
+        if self.link_lib_modifiers.is_empty() {
+            format!("cargo:rustc-link-lib={}={}", kind, lib_name)
+        } else {
+            format!(
+                "cargo:rustc-link-lib={}:{}={}",
+                kind,
+                JoinOsStrs {
+                    slice: &self.link_lib_modifiers,
+                    delimiter: ','
+                },
+                lib_name
+            )
+        }
+    }
 
-        check_disabled()?;
+    /// Run the compiler and link the objects into a shared library named
+    /// `name`, returning the path of the library.
+    ///
+    /// The library is named after the conventions of the target: `libname.so`
+    /// on ELF targets, `libname.dylib` on Apple targets and `name.dll` on
+    /// Windows, where an import library (`name.lib` for MSVC, `libname.dll.a`
+    /// otherwise) is written alongside it. See [`Build::soname`] for the name
+    /// recorded in the library.
+    ///
+    /// The objects are linked by the compiler driver, or by `link.exe` for
+    /// MSVC. Cargo metadata is emitted to link the crate against the library
+    /// dynamically.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let plugin = cc::Build::new().file("src/plugin.c").compile_shared("plugin");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a single normal path component, or if one of
+    /// the underlying compiler or linker commands fails.
+    pub fn compile_shared(&self, name: &str) -> PathBuf {
+        match self.try_compile_shared(name) {
+            Ok(path) => path,
+            Err(e) => fail(&e.message),
+        }
+    }
 
-        if objs.len() <= 1 {
-            for obj in objs {
-                let (mut cmd, name) = self.create_compile_object_cmd(obj)?;
-                run(&mut cmd, &name, &self.cargo_output)?;
-            }
+    /// Run the compiler and link the objects into a shared library.
+    ///
+    /// This will return a result instead of panicking; see
+    /// [`Self::compile_shared()`] for the complete description.
+    pub fn try_compile_shared(&self, name: &str) -> Result<PathBuf, Error> {
+        check_output_name(name, "compile_shared")?;
 
-            return Ok(());
-        }
+        let dst = self.get_out_dir()?;
+        let objects = self.compile_files(&dst)?;
 
-        // Limit our parallelism globally with a jobserver.
-        let mut tokens = parallel::job_token::ActiveJobTokenServer::new();
+        let target = self.get_target()?;
+        let (lib_file, implib_file) = if target.os == "windows" {
+            let implib = if target.env == "msvc" {
+                format!("{}.lib", name)
+            } else {
+                format!("lib{}.dll.a", name)
+            };
+            (format!("{}.dll", name), Some(implib))
+        } else if target.vendor == "apple" {
+            (format!("lib{}.dylib", name), None)
+        } else {
+            (format!("lib{}.so", name), None)
+        };
+        let lib = dst.join(&lib_file);
 
-        // When compiling objects in parallel we do a few dirty tricks to speed
-        // things up:
-        //
+        let (mut cmd, program) = self.try_get_linker_and_name()?;
+        if self.try_get_compiler()?.is_like_msvc() {
+            let mut out = OsString::from("-out:");
+            out.push(&lib);
+            cmd.arg("-nologo").arg("-dll").arg(out);
+            if let Some(implib_file) = &implib_file {
+                let mut implib = OsString::from("-implib:");
+                implib.push(dst.join(implib_file));
+                cmd.arg(implib);
+            }
+        } else {
+            if target.vendor == "apple" {
+                let install_name = match &self.soname {
+                    Some(soname) => soname.to_string(),
+                    None => format!("@rpath/{}", lib_file),
+                };
+                cmd.arg("-dynamiclib")
+                    .arg(format!("-Wl,-install_name,{}", install_name));
+            } else {
+                cmd.arg("-shared");
+                if let Some(implib_file) = &implib_file {
+                    let mut implib = OsString::from("-Wl,--out-implib,");
+                    implib.push(dst.join(implib_file));
+                    cmd.arg(implib);
+                } else {
+                    let soname = self.soname.as_deref().unwrap_or(&lib_file);
+                    cmd.arg(format!("-Wl,-soname,{}", soname));
+                }
+            }
+            cmd.arg("-o").arg(&lib);
+        }
+        cmd.args(objects.iter().map(|obj| &obj.dst))
+            .args(self.objects.iter().map(std::ops::Deref::deref));
+        self.run_step(&mut cmd, &program)?;
+
+        self.cargo_output
+            .print_metadata(&self.link_lib_metadata("dylib", name));
+        self.cargo_output.print_metadata(&format_args!(
+            "cargo:rustc-link-search=native={}",
+            dst.display()
+        ));
+
+        Ok(lib)
+    }
+
+    /// Run the compiler and link the objects into an executable named `name`
+    /// (with `.exe` appended on Windows), returning the path of the
+    /// executable.
+    ///
+    /// The objects are linked by the linker from [`Build::get_linker`]. No
+    /// Cargo metadata is emitted, as the executable is meant to be run by the
+    /// build script itself; to build such helper programs while
+    /// cross-compiling, set the target to the host.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::process::Command;
+    ///
+    /// let host = std::env::var("HOST").unwrap();
+    /// let gen = cc::Build::new()
+    ///     .target(&host)
+    ///     .file("tools/gen_tables.c")
+    ///     .link_executable("gen_tables");
+    /// let status = Command::new(gen).status().unwrap();
+    /// assert!(status.success());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a single normal path component, or if one of
+    /// the underlying compiler or linker commands fails.
+    pub fn link_executable(&self, name: &str) -> PathBuf {
+        match self.try_link_executable(name) {
+            Ok(path) => path,
+            Err(e) => fail(&e.message),
+        }
+    }
+
+    /// Run the compiler and link the objects into an executable.
+    ///
+    /// This will return a result instead of panicking; see
+    /// [`Self::link_executable()`] for the complete description.
+    pub fn try_link_executable(&self, name: &str) -> Result<PathBuf, Error> {
+        check_output_name(name, "link_executable")?;
+
+        let dst = self.get_out_dir()?;
+        let objects = self.compile_files(&dst)?;
+
+        let target = self.get_target()?;
+        let exe = if target.os == "windows" {
+            dst.join(format!("{}.exe", name))
+        } else {
+            dst.join(name)
+        };
+
+        let (mut cmd, program) = self.try_get_linker_and_name()?;
+        if self.try_get_compiler()?.is_like_msvc() {
+            let mut out = OsString::from("-out:");
+            out.push(&exe);
+            cmd.arg("-nologo").arg(out);
+        } else {
+            cmd.arg("-o").arg(&exe);
+        }
+        cmd.args(objects.iter().map(|obj| &obj.dst))
+            .args(self.objects.iter().map(std::ops::Deref::deref));
+        self.run_step(&mut cmd, &program)?;
+
+        Ok(exe)
+    }
+
+    /// Run the compiler, generating intermediate files, but without linking
+    /// them into an archive file.
+    ///
+    /// This will return a list of compiled object files, in the same order
+    /// as they were passed in as `file`/`files` methods.
+    pub fn compile_intermediates(&self) -> Vec<PathBuf> {
+        match self.try_compile_intermediates() {
+            Ok(v) => v,
+            Err(e) => fail(&e.message),
+        }
+    }
+
+    /// Run the compiler, generating intermediate files, but without linking
+    /// them into an archive file.
+    ///
+    /// This will return a result instead of panicking; see `compile_intermediates()` for the complete description.
+    pub fn try_compile_intermediates(&self) -> Result<Vec<PathBuf>, Error> {
+        let dst = self.get_out_dir()?;
+        let objects = self.compile_files(&dst)?;
+
+        Ok(objects.into_iter().map(|v| v.dst).collect())
+    }
+
+    /// The warnings and errors the compiler reported so far, in the order
+    /// they were printed.
+    ///
+    /// These are collected by every compilation of this `Build` and its
+    /// clones, and each is also forwarded as one cargo warning. The output of
+    /// the compiler is parsed as text, unless machine-readable diagnostics
+    /// are enabled with [`Build::machine_readable_diagnostics`].
+    ///
+    /// Nothing is collected if [`cargo_warnings`](Build::cargo_warnings) is
+    /// disabled, as the compiler's stderr is discarded then. MSVC prints its
+    /// diagnostics to stdout, so they aren't collected either.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut build = cc::Build::new();
+    /// build.file("src/foo.c");
+    /// let result = build.try_compile("foo");
+    /// for diagnostic in build.diagnostics() {
+    ///     if diagnostic.severity() == cc::Severity::Error {
+    ///         eprintln!("{}", diagnostic);
+    ///     }
+    /// }
+    /// result.unwrap();
+    /// ```
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.build_cache.diagnostics.read().unwrap().clone()
+    }
+
+    /// Compile every file into an object in `dst`, returning the objects to
+    /// link in.
+    fn compile_files(&self, dst: &Path) -> Result<Vec<Object>, Error> {
+        self.check_linker_plugin_lto()?;
+        let files = self.unity_files(dst)?;
+        let mut objects = objects_from_files(&files, dst, self.object_root()?.as_deref())?;
+        let pch = self.precompile_header(dst)?;
+        self.compile_objects(&objects, pch.as_ref())?;
+        self.verify_reproducible(&objects, pch.as_ref())?;
+        objects.extend(pch.and_then(|pch| pch.object));
+        Ok(objects)
+    }
+
+    /// Compile `objs` a second time and check that they come out the same, if
+    /// `CC_VERIFY_REPRODUCIBLE` is set.
+    ///
+    /// The second pass runs through [`Build::compile_objects`] like the first,
+    /// in parallel if enabled, but quietly and without updating the
+    /// compilation database, the incremental stamps or `rerun-if-changed`.
+    /// The `CC_TIMINGS` report records the second compilation of each object.
+    fn verify_reproducible(
+        &self,
+        objs: &[Object],
+        pch: Option<&PrecompiledHeader>,
+    ) -> Result<(), Error> {
+        match self.getenv("CC_VERIFY_REPRODUCIBLE") {
+            Some(v) if &*v != "0" && &*v != "false" && !v.is_empty() => {}
+            _ => return Ok(()),
+        }
+        let first = objs
+            .iter()
+            .map(|obj| digest(&obj.dst))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut again = self.clone();
+        // The diagnostics were already forwarded by the first compilation.
+        again.cargo_output.warnings = false;
+        again.cargo_output.output = OutputKind::Discard;
+        again.incremental = false;
+        again.compile_commands = Some(false);
+        again.emit_rerun_if_changed = false;
+        again.compile_objects(objs, pch)?;
+
+        let mut differing = String::new();
+        for (obj, first) in objs.iter().zip(first) {
+            let second = digest(&obj.dst)?;
+            if first != second {
+                differing.push_str(&format!(
+                    "\n  {} ({}): {:016x} then {:016x}",
+                    obj.src.display(),
+                    obj.dst.display(),
+                    first,
+                    second
+                ));
+            }
+        }
+
+        if differing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::NotReproducible,
+                format!("objects differ when compiled again:{}", differing),
+            ))
+        }
+    }
+
+    /// The files to compile, with those that can be grouped by
+    /// [`Build::unity_build`] replaced by unity files in `dst`.
+    fn unity_files(&self, dst: &Path) -> Result<Vec<Arc<Path>>, Error> {
+        let batch_size = match self.unity_batch_size {
+            Some(batch_size) if batch_size > 1 && !self.cuda => batch_size,
+            _ => return Ok(self.files.clone()),
+        };
+
+        let mut files = Vec::new();
+        let mut c_files = Vec::new();
+        let mut cpp_files = Vec::new();
+        for file in &self.files {
+            let unity = Language::from_path(file).is_some()
+                && self
+                    .file_options(file)
+                    .map_or(true, FileOptions::allows_unity);
+            if !unity {
+                files.push(file.clone());
+            } else if self.file_language(file) == Language::Cpp {
+                cpp_files.push(file);
+            } else {
+                c_files.push(file);
+            }
+        }
+
+        let dir = dst.join("unity");
+        let current_dir = env::current_dir()?;
+        for (group, ext) in [(c_files, "c"), (cpp_files, "cpp")] {
+            for batch in group.chunks(batch_size) {
+                if let [file] = batch {
+                    files.push((*file).clone());
+                    continue;
+                }
+
+                let mut contents = String::new();
+                let mut hasher = hash_map::DefaultHasher::new();
+                for file in batch {
+                    let file = current_dir.join(file);
+                    contents.push_str(&format!("#include \"{}\"\n", file.display()));
+                    file.hash(&mut hasher);
+                }
+                fs::create_dir_all(&dir)?;
+                let unity_file = dir.join(format!("{:016x}.{}", hasher.finish(), ext));
+                write_if_changed(&unity_file, contents.as_bytes())?;
+                files.push(unity_file.into());
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Precompile the header set by [`Build::precompiled_header`] into `dst`,
+    /// unless it is up to date.
+    fn precompile_header(&self, dst: &Path) -> Result<Option<PrecompiledHeader>, Error> {
+        let (pch, mut cmd) = match self.plan_precompiled_header(dst)? {
+            Some(plan) => plan,
+            None => return Ok(None),
+        };
+        check_disabled()?;
+
+        let output = &pch.header.dst;
+        let cmdline = format!("{:?}", cmd);
+        let outputs_exist = pch
+            .object
+            .as_ref()
+            .map_or(true, |object| object.dst.exists());
+        if outputs_exist
+            && depfile::is_up_to_date(&pch.header.src, output, &cmdline, pch.depfile, &[])
+        {
+            self.cargo_output.print_debug(&format_args!(
+                "{} is up to date, skipping precompilation",
+                output.display()
+            ));
+        } else {
+            depfile::remove_stamp(output);
+            let program = Path::new(cmd.get_program());
+            let name = PathBuf::from(program.file_name().unwrap_or(program.as_os_str()));
+            self.run_step(&mut cmd, &name)?;
+            depfile::write_stamp(output, &cmdline)?;
+        }
+
+        Ok(Some(pch))
+    }
+
+    /// The header set by [`Build::precompiled_header`], and the command
+    /// precompiling it into `dst`.
+    fn plan_precompiled_header(
+        &self,
+        dst: &Path,
+    ) -> Result<Option<(PrecompiledHeader, Command)>, Error> {
+        let header = match &self.precompiled_header {
+            Some(header) => env::current_dir()?.join(header),
+            None => return Ok(None),
+        };
+        if self.cuda {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                "precompiled headers are not supported for CUDA",
+            ));
+        }
+
+        let file_name = header.file_name().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidArgument,
+                "No file_name for precompiled header path!",
+            )
+        })?;
+        let language = self.language();
+        let compiler = self.try_get_compiler()?;
+
+        // Keep the precompiled headers of different builds sharing `OUT_DIR`
+        // apart.
+        let mut hasher = hash_map::DefaultHasher::new();
+        header.hash(&mut hasher);
+        language.hash(&mut hasher);
+        let dir = dst.join(format!("pch-{:016x}", hasher.finish()));
+        fs::create_dir_all(&dir)?;
+
+        let mut cmd = compiler.to_command();
+        for (a, b) in self.env.iter() {
+            cmd.env(a, b);
+        }
+        let (output, args, object) = match compiler.family {
+            ToolFamily::Msvc { .. } => {
+                // MSVC precompiles the headers included by a source file, up
+                // to and including the one named by `-Yc`.
+                let ext = match language {
+                    Language::C => "c",
+                    Language::Cpp => "cpp",
+                };
+                let src = dir.join(file_name).with_extension(format!("pch.{}", ext));
+                write_if_changed(
+                    &src,
+                    format!("#include \"{}\"\n", header.display()).as_bytes(),
+                )?;
+                let output = dir.join(file_name).with_extension("pch");
+                let obj = dir.join(file_name).with_extension("pch.o");
+
+                let mut yc = OsString::from("-Yc");
+                yc.push(&header);
+                let mut fp = OsString::from("-Fp");
+                fp.push(&output);
+                let mut fo = OsString::from("-Fo");
+                fo.push(&obj);
+                cmd.arg("-c").arg(yc).arg(&fp).arg(fo);
+
+                let mut yu = OsString::from("-Yu");
+                yu.push(&header);
+                let mut fi = OsString::from("-FI");
+                fi.push(&header);
+                (output, vec![yu, fp, fi], Some(Object::new(src, obj)))
+            }
+            ToolFamily::Clang { .. } => {
+                let mut output = dir.join(file_name).into_os_string();
+                output.push(".pch");
+                let output = PathBuf::from(output);
+                cmd.arg("-x")
+                    .arg(language.header_kind())
+                    .arg("-o")
+                    .arg(&output);
+                let args = vec!["-include-pch".into(), output.clone().into()];
+                (output, args, None)
+            }
+            ToolFamily::Gnu => {
+                // GCC uses `<header>.gch` in place of a header it includes,
+                // so include a stub next to the precompiled header. If the
+                // latter turns out to be unusable, the stub includes the real
+                // header instead.
+                let stub = dir.join(file_name);
+                write_if_changed(
+                    &stub,
+                    format!("#include \"{}\"\n", header.display()).as_bytes(),
+                )?;
+                let mut output = stub.clone().into_os_string();
+                output.push(".gch");
+                let output = PathBuf::from(output);
+                cmd.arg("-x")
+                    .arg(language.header_kind())
+                    .arg("-o")
+                    .arg(&output);
+                (output, vec!["-include".into(), stub.into()], None)
+            }
+        };
+
+        let depfile = DepfileFormat::for_tool(&compiler);
+        depfile.add_args(&mut cmd, &compiler, &output);
+        cmd.arg(match &object {
+            Some(object) => &object.src,
+            None => &header,
+        });
+
+        let pch = PrecompiledHeader {
+            language,
+            args,
+            header: Object::new(header, output),
+            depfile,
+            object,
+        };
+        Ok(Some((pch, cmd)))
+    }
+
+    fn compile_objects(
+        &self,
+        objs: &[Object],
+        pch: Option<&PrecompiledHeader>,
+    ) -> Result<(), Error> {
+        check_disabled()?;
+
+        let compile_commands_dir = self.compile_commands_dir()?;
+
+        let mut cmds = Vec::with_capacity(objs.len());
+        let mut stamps = Vec::new();
+        let mut depfiles = Vec::with_capacity(objs.len());
+        let mut compile_commands = Vec::new();
+        if let Some(pch) = pch {
+            depfiles.push((&pch.header, Some(pch.depfile)));
+        }
+        // Objects must be newer than the precompiled header they were
+        // compiled with to be up to date.
+        let extra_deps: Vec<&Path> = pch.iter().map(|pch| &*pch.header.dst).collect();
+        for obj in objs {
+            let cmd = self.create_compile_object_cmd(obj, pch, true)?;
+            depfiles.push((obj, cmd.depfile));
+            if compile_commands_dir.is_some() {
+                let mut skip_args = cmd.diagnostics.args().to_vec();
+                if cmd.time_trace.is_some() {
+                    skip_args.push("-ftime-trace");
+                }
+                compile_commands.push(compile_commands::entry(
+                    &cmd.cmd, &obj.src, &obj.dst, &skip_args,
+                )?);
+            }
+            if let (true, Some(format)) = (self.incremental, cmd.depfile) {
+                let Object { src, dst } = cmd.obj;
+                let cmdline = format!("{:?}", cmd.cmd);
+                if depfile::is_up_to_date(src, dst, &cmdline, format, &extra_deps) {
+                    self.cargo_output.print_debug(&format_args!(
+                        "{} is up to date, skipping compilation",
+                        dst.display()
+                    ));
+                    continue;
+                }
+                depfile::remove_stamp(dst);
+                stamps.push((dst, cmdline));
+            }
+            cmds.push(cmd);
+        }
+
+        if let Some(dir) = compile_commands_dir {
+            if let Err(e) = compile_commands::write(&dir, &compile_commands) {
+                self.cargo_output.print_warning(&format_args!(
+                    "failed to write compile_commands.json to {}: {}",
+                    dir.display(),
+                    e
+                ));
+            }
+        }
+
+        let result = self.run_compile_commands(cmds);
+        self.write_timings();
+        result?;
+
+        for (dst, cmdline) in stamps {
+            depfile::write_stamp(dst, &cmdline)?;
+        }
+
+        if self.emit_rerun_if_changed {
+            self.print_rerun_if_changed(&depfiles)?;
+        }
+
+        Ok(())
+    }
+
+    /// The directory of the `compile_commands.json` to record the compile
+    /// commands in, if enabled.
+    fn compile_commands_dir(&self) -> Result<Option<PathBuf>, Error> {
+        if self.compile_commands == Some(false) {
+            return Ok(None);
+        }
+        if let Some(dir) = self.getenv("CC_COMPILE_COMMANDS_DIR") {
+            if !dir.is_empty() {
+                return Ok(Some(PathBuf::from(&*dir)));
+            }
+        }
+        if self.compile_commands != Some(true) {
+            return Ok(None);
+        }
+
+        // `OUT_DIR` is `<target dir>/[<target>/]<profile>/build/<pkg>-<hash>/out`.
+        let out_dir = self.get_out_dir()?;
+        let profile_dir = out_dir
+            .ancestors()
+            .find(|dir| dir.file_name() == Some(OsStr::new("build")))
+            .and_then(Path::parent)
+            .unwrap_or(&out_dir);
+        Ok(Some(profile_dir.to_path_buf()))
+    }
+
+    /// Emit `cargo:rerun-if-changed` for the sources of `objs` and the
+    /// headers recorded in their depfiles.
+    fn print_rerun_if_changed(
+        &self,
+        objs: &[(&Object, Option<DepfileFormat>)],
+    ) -> Result<(), Error> {
+        let compiler = self.try_get_compiler()?;
+        let include = if compiler.is_like_msvc() {
+            self.getenv("INCLUDE")
+        } else {
+            None
+        };
+        let system_dirs = depfile::system_include_dirs(&compiler, include.as_deref());
+
+        let mut seen = HashSet::new();
+        for &(obj, format) in objs {
+            let deps = match format {
+                Some(format) => match format.read(&obj.dst) {
+                    Ok(deps) => deps
+                        .into_iter()
+                        .filter(|dep| !depfile::is_system_header(dep, &system_dirs, format))
+                        .collect(),
+                    Err(e) => {
+                        self.cargo_output.print_warning(&format_args!(
+                            "failed to read the dependencies of {}: {}",
+                            obj.src.display(),
+                            e
+                        ));
+                        Vec::new()
+                    }
+                },
+                None => Vec::new(),
+            };
+            for path in std::iter::once(obj.src.clone()).chain(deps) {
+                if seen.insert(path.clone()) {
+                    self.cargo_output
+                        .print_metadata(&format_args!("cargo:rerun-if-changed={}", path.display()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    fn run_compile_commands(&self, cmds: Vec<CompileCommand<'_>>) -> Result<(), Error> {
+        use std::{cell::Cell, mem, thread};
+
+        use parallel::async_executor::{block_on, YieldOnce};
+
+        if cmds.len() <= 1 {
+            for mut cmd in cmds {
+                self.run_compile_command(&mut cmd)?;
+            }
+
+            return Ok(());
+        }
+
+        // Limit our parallelism globally with a jobserver.
+        let mut tokens = parallel::job_token::ActiveJobTokenServer::new();
+
+        // When compiling objects in parallel we do a few dirty tricks to speed
+        // things up:
+        //
         // * First is that we use the `jobserver` crate to limit the parallelism
         //   of this build script. The `jobserver` crate will use a jobserver
         //   configured by Cargo for build scripts to ensure that parallelism is
@@ -1622,18 +3270,21 @@ impl Build {
         // With all that in mind we compile all objects in a loop here, after we
         // acquire the appropriate tokens, Once all objects have been compiled
         // we wait on all the processes and propagate the results of compilation.
+        //
+        // A `Build::runner` blocks until its command is done, so it is called
+        // on a thread of its own instead of spawning the process.
 
         let pendings = Cell::new(Vec::<(
-            Command,
-            Cow<'static, Path>,
-            KillOnDrop,
+            CompileCommand<'_>,
+            Running,
             parallel::job_token::JobToken,
+            Timer,
         )>::new());
         let is_disconnected = Cell::new(false);
         let has_made_progress = Cell::new(false);
 
         let wait_future = async {
-            let mut error = None;
+            let mut failures = Vec::new();
             // Buffer the stdout
             let mut stdout = io::BufWriter::with_capacity(128, io::stdout());
 
@@ -1649,30 +3300,54 @@ impl Build {
 
                 cell_update(&pendings, |mut pendings| {
                     // Try waiting on them.
-                    pendings.retain_mut(|(cmd, program, child, _token)| {
-                        match try_wait_on_child(
-                            cmd,
-                            program,
-                            &mut child.0,
-                            &mut stdout,
-                            &mut child.1,
-                        ) {
-                            Ok(Some(())) => {
+                    pendings.retain_mut(|(cmd, running, _token, timer)| {
+                        let result = match running {
+                            Running::Child(child) => try_wait_on_child(
+                                &cmd.cmd,
+                                &cmd.program,
+                                &mut child.0,
+                                &mut stdout,
+                                &mut child.1,
+                            )
+                            .map(|done| done.map(|()| child.1.take_captured())),
+                            Running::Runner(handle) => match handle.take_if_finished() {
+                                Some((command, output)) => {
+                                    cmd.cmd = command;
+                                    handle_output(
+                                        &cmd.cmd,
+                                        &cmd.program,
+                                        &self.cargo_output,
+                                        output,
+                                        false,
+                                    )
+                                    .map(Some)
+                                }
+                                None => Ok(None),
+                            },
+                        };
+                        if !matches!(result, Ok(None)) {
+                            self.record_compile_timing(*timer, cmd);
+                        }
+                        match result {
+                            Ok(Some(stderr)) => {
                                 // Task done, remove the entry
                                 has_made_progress.set(true);
+                                self.report_diagnostics(stderr.as_deref());
                                 false
                             }
                             Ok(None) => true, // Task still not finished, keep the entry
                             Err(err) => {
                                 // Task fail, remove the entry.
-                                // Since we can only return one error, log the error to make
-                                // sure users always see all the compilation failures.
                                 has_made_progress.set(true);
 
-                                if self.cargo_output.warnings {
+                                self.report_diagnostics(err.stderr());
+                                // Log the error right away, as the other
+                                // objects may take a while to compile.
+                                if self.on_error == OnError::KeepGoing && self.cargo_output.warnings
+                                {
                                     let _ = writeln!(stdout, "cargo:warning={}", err);
                                 }
-                                error = Some(err);
+                                failures.push((&*cmd.obj.src, err));
 
                                 false
                             }
@@ -1682,27 +3357,53 @@ impl Build {
                     pendings
                 });
 
+                // Returning an error stops spawning, and kills the running
+                // compilers as `pendings` is dropped. Only one error can be
+                // returned, so log the others.
+                if self.on_error == OnError::FailFast && !failures.is_empty() {
+                    let mut errors = failures.drain(..).map(|(_, err)| err);
+                    let first = errors.next().unwrap();
+                    if self.cargo_output.warnings {
+                        for err in errors {
+                            let _ = writeln!(stdout, "cargo:warning={}", err);
+                        }
+                    }
+                    break Err(first);
+                }
                 if pendings_is_empty && is_disconnected.get() {
-                    break if let Some(err) = error {
-                        Err(err)
-                    } else {
-                        Ok(())
-                    };
+                    break failures_to_result(failures);
                 }
 
                 YieldOnce::default().await;
             }
         };
         let spawn_future = async {
-            for obj in objs {
-                let (mut cmd, program) = self.create_compile_object_cmd(obj)?;
+            for mut cmd in cmds {
+                let waiting = Instant::now();
                 let token = tokens.acquire().await?;
-                let mut child = spawn(&mut cmd, &program, &self.cargo_output)?;
-                let mut stderr_forwarder = StderrForwarder::new(&mut child);
-                stderr_forwarder.set_non_blocking()?;
+                let timer = Timer::start(waiting.elapsed());
+                let running = match &self.runner {
+                    Some(runner) => {
+                        self.cargo_output
+                            .print_debug(&format_args!("running: {:?}", cmd.cmd));
+                        let runner = Arc::clone(runner);
+                        let mut command = mem::replace(&mut cmd.cmd, Command::new(&*cmd.program));
+                        Running::Runner(RunnerThread(Some(thread::spawn(move || {
+                            let output = runner.run(&mut command);
+                            (command, output)
+                        }))))
+                    }
+                    None => {
+                        let mut child = spawn(&mut cmd.cmd, &cmd.program, &self.cargo_output)?;
+                        // The diagnostics are reported once the compiler is done.
+                        let mut stderr_forwarder = StderrForwarder::new(&mut child, false);
+                        stderr_forwarder.set_non_blocking()?;
+                        Running::Child(KillOnDrop(child, stderr_forwarder))
+                    }
+                };
 
                 cell_update(&pendings, |mut pendings| {
-                    pendings.push((cmd, program, KillOnDrop(child, stderr_forwarder), token));
+                    pendings.push((cmd, running, token, timer));
                     pendings
                 });
 
@@ -1715,8 +3416,28 @@ impl Build {
 
         return block_on(wait_future, spawn_future, &has_made_progress);
 
+        enum Running {
+            Child(KillOnDrop),
+            Runner(RunnerThread),
+        }
+
         struct KillOnDrop(Child, StderrForwarder);
 
+        struct RunnerThread(Option<thread::JoinHandle<(Command, io::Result<Output>)>>);
+
+        impl RunnerThread {
+            /// The command and its output, once the runner is done with it.
+            fn take_if_finished(&mut self) -> Option<(Command, io::Result<Output>)> {
+                if !self.0.as_ref()?.is_finished() {
+                    return None;
+                }
+                match self.0.take()?.join() {
+                    Ok(done) => Some(done),
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+        }
+
         impl Drop for KillOnDrop {
             fn drop(&mut self) {
                 let child = &mut self.0;
@@ -1737,21 +3458,39 @@ impl Build {
     }
 
     #[cfg(not(feature = "parallel"))]
-    fn compile_objects(&self, objs: &[Object]) -> Result<(), Error> {
-        check_disabled()?;
-
-        for obj in objs {
-            let (mut cmd, name) = self.create_compile_object_cmd(obj)?;
-            run(&mut cmd, &name, &self.cargo_output)?;
+    fn run_compile_commands(&self, cmds: Vec<CompileCommand<'_>>) -> Result<(), Error> {
+        let mut failures = Vec::new();
+        for mut cmd in cmds {
+            if let Err(err) = self.run_compile_command(&mut cmd) {
+                if self.on_error == OnError::FailFast {
+                    return Err(err);
+                }
+                self.cargo_output.print_warning(&err);
+                failures.push((&*cmd.obj.src, err));
+            }
         }
 
-        Ok(())
+        failures_to_result(failures)
     }
 
-    fn create_compile_object_cmd(
+    /// The command compiling `obj`, with the flags only needed when cc runs
+    /// it itself (to parse its diagnostics or time it) if `run_by_cc`.
+    fn create_compile_object_cmd<'a>(
         &self,
-        obj: &Object,
-    ) -> Result<(Command, Cow<'static, Path>), Error> {
+        obj: &'a Object,
+        pch: Option<&PrecompiledHeader>,
+        run_by_cc: bool,
+    ) -> Result<CompileCommand<'a>, Error> {
+        if let Some(build) = self.file_build(&obj.src)? {
+            // The header was precompiled with the build-wide options, and
+            // compilers reject it if the definitions or optimizations differ.
+            let changes_compilation = self
+                .file_options(&obj.src)
+                .map_or(false, FileOptions::changes_compilation);
+            let pch = pch.filter(|_| !changes_compilation);
+            return build.create_compile_object_cmd(obj, pch, run_by_cc);
+        }
+
         let asm_ext = AsmFileExt::from_path(&obj.src);
         let is_asm = asm_ext.is_some();
         let target = self.get_target()?;
@@ -1797,27 +3536,314 @@ impl Build {
         if !is_assembler_msvc || !is_arm {
             cmd.arg("-c");
         }
-        if self.cuda && self.cuda_file_count() > 1 {
-            cmd.arg("--device-c");
+        if self.cuda && self.cuda_file_count() > 1 {
+            cmd.arg("--device-c");
+        }
+        if is_asm {
+            cmd.args(self.asm_flags.iter().map(std::ops::Deref::deref));
+        }
+
+        // MASM and NVCC can't write depfiles.
+        let depfile =
+            if (self.incremental || self.emit_rerun_if_changed) && !is_assembler_msvc && !self.cuda
+            {
+                let format = DepfileFormat::for_tool(&compiler);
+                format.add_args(&mut cmd, &compiler, &obj.dst);
+                Some(format)
+            } else {
+                None
+            };
+
+        if let Some(pch) = pch {
+            if pch.language == self.language() && !is_asm {
+                cmd.args(&pch.args);
+            }
+        }
+
+        let diagnostics = if is_assembler_msvc || !run_by_cc {
+            DiagnosticsFormat::Text
+        } else {
+            self.diagnostics_format(&compiler, &target)
+        };
+        cmd.args(diagnostics.args());
+
+        let time_trace = if run_by_cc
+            && self.timings_path().is_some()
+            && matches!(compiler.family, ToolFamily::Clang { .. })
+            && !is_asm
+            && !self.cuda
+            && self
+                .is_flag_supported_inner(OsStr::new("-ftime-trace"), &compiler, &target)
+                .unwrap_or(false)
+        {
+            cmd.arg("-ftime-trace");
+            Some(timings::time_trace_path(&obj.dst))
+        } else {
+            None
+        };
+
+        if compiler.supports_path_delimiter() && !is_assembler_msvc {
+            // #513: For `clang-cl`, separate flags/options from the input file.
+            // When cross-compiling macOS -> Windows, this avoids interpreting
+            // common `/Users/...` paths as the `/U` flag and triggering
+            // `-Wslash-u-filename` warning.
+            cmd.arg("--");
+        }
+        cmd.arg(&obj.src);
+
+        if cfg!(target_os = "macos") {
+            self.fix_env_for_apple_os(&mut cmd)?;
+        }
+
+        Ok(CompileCommand {
+            obj,
+            cmd,
+            program: name,
+            depfile,
+            diagnostics,
+            time_trace,
+        })
+    }
+
+    /// The format to ask `compiler` to print its diagnostics in, see
+    /// [`Build::diagnostics`].
+    fn diagnostics_format(&self, compiler: &Tool, target: &TargetInfo<'_>) -> DiagnosticsFormat {
+        // Diagnostics are only parsed when forwarded, and a format chosen in
+        // the flags is left alone.
+        if !self.machine_readable_diagnostics
+            || !self.cargo_output.warnings
+            || self.cuda
+            || compiler
+                .args()
+                .iter()
+                .any(|arg| arg.to_string_lossy().starts_with("-fdiagnostics-format"))
+        {
+            return DiagnosticsFormat::Text;
+        }
+
+        let supported = |format: DiagnosticsFormat| {
+            // These print the (empty) list of diagnostics, so only check that
+            // the compiler accepts them.
+            self.is_flag_supported_with(format.args()[0].as_ref(), compiler, target, true)
+                .unwrap_or(false)
+        };
+        match compiler.family {
+            ToolFamily::Gnu if supported(DiagnosticsFormat::GccSarif) => {
+                DiagnosticsFormat::GccSarif
+            }
+            ToolFamily::Gnu if supported(DiagnosticsFormat::GccJson) => DiagnosticsFormat::GccJson,
+            // Clang warns that its SARIF output is unstable, through a warning
+            // that exists in exactly the versions supporting it.
+            ToolFamily::Clang { .. }
+                if self
+                    .is_flag_supported_inner(
+                        OsStr::new("-Wno-sarif-format-unstable"),
+                        compiler,
+                        target,
+                    )
+                    .unwrap_or(false) =>
+            {
+                DiagnosticsFormat::ClangSarif
+            }
+            _ => DiagnosticsFormat::Text,
+        }
+    }
+
+    /// Forward the diagnostics a compiler printed to `stderr` as cargo
+    /// warnings, and remember them for [`Build::diagnostics`].
+    ///
+    /// Each diagnostic is forwarded as one cargo warning with its location,
+    /// severity, message and notes, whatever format it was printed in.
+    fn report_diagnostics(&self, stderr: Option<&[u8]>) {
+        let stderr = match stderr {
+            Some(stderr) => String::from_utf8_lossy(stderr),
+            None => return,
+        };
+        let (diagnostics, other) = diagnostics::parse(&stderr);
+        for diagnostic in &diagnostics {
+            self.cargo_output
+                .print_warning(&diagnostic.to_string().replace('\n', " "));
+        }
+        // These are mostly summaries printed last, such as `1 warning
+        // generated.`
+        for line in other {
+            self.cargo_output.print_warning(&line);
+        }
+        self.build_cache
+            .diagnostics
+            .write()
+            .unwrap()
+            .extend(diagnostics);
+    }
+
+    /// Run a step of the build other than compiling an object, with the
+    /// [`Build::runner`] if any.
+    fn run_step(&self, cmd: &mut Command, program: impl AsRef<Path>) -> Result<(), Error> {
+        let program = program.as_ref();
+        match &self.runner {
+            Some(runner) => {
+                run_with_runner(cmd, program, &self.cargo_output, &**runner, true).map(drop)
+            }
+            None => run(cmd, program, &self.cargo_output),
+        }
+    }
+
+    /// Run a compile command, reporting the diagnostics it prints.
+    fn run_compile_command(&self, cmd: &mut CompileCommand<'_>) -> Result<(), Error> {
+        let timer = Timer::start(Duration::ZERO);
+        let stderr = match &self.runner {
+            Some(runner) => run_with_runner(
+                &mut cmd.cmd,
+                &cmd.program,
+                &self.cargo_output,
+                &**runner,
+                false,
+            ),
+            None => run_capture_stderr(&mut cmd.cmd, &cmd.program, &self.cargo_output),
+        };
+        let result = match stderr {
+            Ok(stderr) => {
+                self.report_diagnostics(stderr.as_deref());
+                Ok(())
+            }
+            Err(e) => {
+                self.report_diagnostics(e.stderr());
+                Err(e)
+            }
+        };
+        self.record_compile_timing(timer, cmd);
+        result
+    }
+
+    /// The file to write the timing report to, from `CC_TIMINGS`.
+    fn timings_path(&self) -> Option<PathBuf> {
+        self.getenv("CC_TIMINGS")
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(&*path))
+    }
+
+    /// Record how long compiling `cmd` took, see [`Build::write_timings`].
+    fn record_compile_timing(&self, timer: Timer, cmd: &CompileCommand<'_>) {
+        if self.timings_path().is_some() {
+            let src = env::current_dir()
+                .map(|dir| dir.join(&cmd.obj.src))
+                .unwrap_or_else(|_| cmd.obj.src.clone());
+            let step = timer.stop(
+                "compile",
+                Some(&src),
+                &cmd.obj.dst,
+                &cmd.cmd,
+                cmd.time_trace.as_deref(),
+            );
+            self.build_cache.timings.write().unwrap().push(step);
+        }
+    }
+
+    /// Add the steps timed so far to the report requested with `CC_TIMINGS`.
+    fn write_timings(&self) {
+        let path = match self.timings_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let steps = std::mem::take(&mut *self.build_cache.timings.write().unwrap());
+        if let Err(e) = timings::write(&path, &steps) {
+            self.cargo_output.print_warning(&format_args!(
+                "failed to write the timings to {}: {}",
+                path.display(),
+                e
+            ));
         }
-        if is_asm {
-            cmd.args(self.asm_flags.iter().map(std::ops::Deref::deref));
+    }
+
+    /// The options `src` was added with, if any.
+    fn file_options(&self, src: &Path) -> Option<&FileOptions> {
+        self.file_options
+            .iter()
+            .rev()
+            .find(|(file, _)| **file == *src)
+            .map(|(_, options)| &**options)
+    }
+
+    fn language(&self) -> Language {
+        if self.cpp {
+            Language::Cpp
+        } else {
+            Language::C
         }
+    }
 
-        if compiler.supports_path_delimiter() && !is_assembler_msvc {
-            // #513: For `clang-cl`, separate flags/options from the input file.
-            // When cross-compiling macOS -> Windows, this avoids interpreting
-            // common `/Users/...` paths as the `/U` flag and triggering
-            // `-Wslash-u-filename` warning.
-            cmd.arg("--");
+    /// The language `src` is compiled as.
+    fn file_language(&self, src: &Path) -> Language {
+        if let Some(language) = self
+            .file_language
+            .or_else(|| self.file_options(src).and_then(|options| options.language))
+        {
+            return language;
         }
-        cmd.arg(&obj.src);
+        match Language::from_path(src) {
+            Some(Language::Cpp) if !self.cuda => Language::Cpp,
+            _ => self.language(),
+        }
+    }
 
-        if cfg!(target_os = "macos") {
-            self.fix_env_for_apple_os(&mut cmd)?;
+    /// Whether any file is compiled as C++.
+    fn uses_cpp(&self) -> bool {
+        self.cpp
+            || self
+                .files
+                .iter()
+                .any(|file| self.file_language(file) == Language::Cpp)
+    }
+
+    /// This configuration, switched to compiling `language`.
+    fn with_language(&self, language: Language) -> Build {
+        let mut build = self.clone();
+        if language != self.language() {
+            build.cpp = language == Language::Cpp;
+            // The standard was meant for the other language.
+            build.std = None;
+        }
+        build
+    }
+
+    /// The configuration for compiling `src`, if it differs from the
+    /// build-wide one.
+    fn file_build(&self, src: &Path) -> Result<Option<Build>, Error> {
+        let options = self.file_options(src);
+        let language = self.file_language(src);
+        if options.is_none() && language == self.language() {
+            return Ok(None);
         }
 
-        Ok((cmd, name))
+        let mut build = self.with_language(language);
+        build.file_options = Vec::new();
+        build.file_language = Some(language);
+        let options = match options {
+            Some(options) => options,
+            None => return Ok(Some(build)),
+        };
+
+        // The extension doesn't tell the compiler the language, so force it.
+        if options.language.is_some() {
+            let compiler = build.get_base_compiler()?;
+            let flags: &[&str] = match (compiler.family, language) {
+                (ToolFamily::Msvc { .. }, Language::C) => &["-TC"],
+                (ToolFamily::Msvc { .. }, Language::Cpp) => &["-TP"],
+                (_, Language::C) => &["-x", "c"],
+                (_, Language::Cpp) => &["-x", "c++"],
+            };
+            build
+                .flags
+                .extend(flags.iter().map(|&flag| OsStr::new(flag).into()));
+        }
+        build.flags.extend(options.flags.iter().cloned());
+        build
+            .definitions
+            .extend(options.definitions.iter().cloned());
+        if let Some(opt_level) = &options.opt_level {
+            build.opt_level = Some(opt_level.clone());
+        }
+        Ok(Some(build))
     }
 
     /// This will return a result instead of panicking; see [`Self::expand()`] for
@@ -1921,7 +3947,12 @@ impl Build {
             self.add_default_flags(&mut cmd, &target, &opt_level)?;
         }
 
-        if let Some(ref std) = self.std {
+        let std = if self.cpp {
+            self.cpp_std.as_ref().or(self.std.as_ref())
+        } else {
+            self.c_std.as_ref().or(self.std.as_ref())
+        };
+        if let Some(std) = std {
             let separator = match cmd.family {
                 ToolFamily::Msvc { .. } => ':',
                 ToolFamily::Gnu | ToolFamily::Clang { .. } => '=',
@@ -1964,6 +3995,11 @@ impl Build {
         if self.inherit_rustflags {
             self.add_inherited_rustflags(&mut cmd, &target)?;
         }
+        self.add_remap_path_prefix(&mut cmd, &target)?;
+        if let Some(epoch) = self.getenv("SOURCE_DATE_EPOCH") {
+            cmd.env
+                .push(("SOURCE_DATE_EPOCH".into(), epoch.to_os_string()));
+        }
 
         for flag in self.flags_supported.iter() {
             if self
@@ -2457,6 +4493,141 @@ impl Build {
         Ok(())
     }
 
+    /// Remap the path prefixes given to rustc with `--remap-path-prefix`, if
+    /// inherited, and to [`Build::remap_path_prefix`], in that order.
+    fn add_remap_path_prefix(&self, cmd: &mut Tool, target: &TargetInfo<'_>) -> Result<(), Error> {
+        let rustflags = match self.getenv("CARGO_ENCODED_RUSTFLAGS") {
+            Some(env) if self.inherit_rustflags => env.to_string_lossy().into_owned(),
+            _ => String::new(),
+        };
+        let rustflags = RustcCodegenFlags::parse(&rustflags)?;
+        let remaps: Vec<(&OsStr, &OsStr)> = rustflags
+            .remap_path_prefix()
+            .iter()
+            .map(|&(from, to)| (OsStr::new(from), OsStr::new(to)))
+            .chain(
+                self.remap_path_prefix
+                    .iter()
+                    .map(|(from, to)| (from.as_os_str(), to.as_os_str())),
+            )
+            .collect();
+        if remaps.is_empty() {
+            return Ok(());
+        }
+
+        let flag = match remap_path_prefix_flag(self, cmd, target) {
+            Some(flag) => flag,
+            None => {
+                self.cargo_output.print_warning(&format_args!(
+                    "Path prefixes are not remapped, as {} does not support it",
+                    cmd.path.display()
+                ));
+                return Ok(());
+            }
+        };
+        for (from, to) in remaps {
+            let mut arg = OsString::from(flag);
+            arg.push(from);
+            arg.push("=");
+            arg.push(to);
+            cmd.push_cc_arg(arg);
+        }
+        Ok(())
+    }
+
+    /// Whether rustc is passed `-Clinker-plugin-lto`, and we inherit it.
+    fn uses_linker_plugin_lto(&self) -> Result<bool, Error> {
+        if !self.inherit_rustflags {
+            return Ok(false);
+        }
+        let env = match self.getenv("CARGO_ENCODED_RUSTFLAGS") {
+            Some(env) => env,
+            None => return Ok(false),
+        };
+        let env = env.to_string_lossy();
+        Ok(RustcCodegenFlags::parse(&env)?.linker_plugin_lto())
+    }
+
+    /// Warn if the bitcode emitted for `-Clinker-plugin-lto` comes from
+    /// another version of LLVM than the one used by rustc.
+    fn check_linker_plugin_lto(&self) -> Result<(), Error> {
+        if !self.uses_linker_plugin_lto()? {
+            return Ok(());
+        }
+        let compiler = self.try_get_compiler()?;
+        if !compiler.is_like_clang() && !compiler.is_like_clang_cl() {
+            // Already warned about when inheriting the flag.
+            return Ok(());
+        }
+
+        let mut cmd = compiler.to_command();
+        for (a, b) in self.env.iter() {
+            cmd.env(a, b);
+        }
+        let clang_version = self.llvm_major_version(
+            cmd.arg("--version"),
+            compiler.path(),
+            clang_llvm_major_version,
+        );
+
+        let rustc = self
+            .getenv("RUSTC")
+            .unwrap_or_else(|| OsStr::new("rustc").into());
+        let rustc_version = self.llvm_major_version(
+            self.cmd(&rustc).arg("-vV"),
+            Path::new(&rustc),
+            rustc_llvm_major_version,
+        );
+
+        match (clang_version, rustc_version) {
+            (Some(clang_version), Some(rustc_version)) if clang_version != rustc_version => {
+                self.cargo_output.print_warning(&format_args!(
+                    "-Clinker-plugin-lto: {} uses LLVM {} but rustc uses LLVM {}, linking may fail",
+                    compiler.path().display(),
+                    clang_version,
+                    rustc_version
+                ));
+            }
+            (Some(_), Some(_)) => {}
+            _ => self.cargo_output.print_debug(&format_args!(
+                "Could not determine the LLVM versions of {} and rustc for -Clinker-plugin-lto",
+                compiler.path().display()
+            )),
+        }
+        Ok(())
+    }
+
+    /// The major version of LLVM used by `program`, parsed by `parse` from
+    /// the output of `cmd`, cached by program.
+    fn llvm_major_version(
+        &self,
+        cmd: &mut Command,
+        program: &Path,
+        parse: fn(&str) -> Option<u32>,
+    ) -> Option<u32> {
+        if let Some(&version) = self
+            .build_cache
+            .llvm_version_cache
+            .read()
+            .unwrap()
+            .get(program)
+        {
+            return version;
+        }
+        // Failing to run the tool only means that we can't check.
+        let mut cargo_output = self.cargo_output.clone();
+        cargo_output.warnings = cargo_output.debug;
+        let version = run_output(cmd, program, &cargo_output)
+            .ok()
+            .and_then(|output| parse(&String::from_utf8_lossy(&output)));
+        self.build_cache
+            .llvm_version_cache
+            .write()
+            .unwrap()
+            .insert(program.into(), version);
+        version
+    }
+
     fn has_flags(&self) -> bool {
         let flags_env_var_name = if self.cpp { "CXXFLAGS" } else { "CFLAGS" };
         let flags_env_var_value = self.getenv_with_target_prefixes(flags_env_var_name);
@@ -2526,28 +4697,41 @@ impl Build {
         // create on the first iteration instead of appending.
         let _ = fs::remove_file(dst);
 
-        // Add objects to the archive in limited-length batches. This helps keep
-        // the length of the command line within a reasonable length to avoid
-        // blowing system limits on limiting platforms like Windows.
-        let objs: Vec<_> = objs
+        let mut objs: Vec<_> = objs
             .iter()
             .map(|o| o.dst.as_path())
             .chain(self.objects.iter().map(std::ops::Deref::deref))
             .collect();
-        for chunk in objs.chunks(100) {
-            self.assemble_progressive(dst, chunk)?;
+        let out_dir = dst.parent().unwrap_or(dst);
+        let members = self.extract_archives(out_dir, lib_name, &objs)?;
+        objs.extend(members.iter().map(PathBuf::as_path));
+        let processed = self.process_symbols(out_dir, lib_name, &objs)?;
+        if let Some(processed) = &processed {
+            objs = processed.iter().map(PathBuf::as_path).collect();
+        }
+        let builtin = self.builtin_archive_format()?;
+        match builtin {
+            Some(format) => self.assemble_builtin(dst, &objs, format)?,
+            // Add objects to the archive in limited-length batches. This helps keep
+            // the length of the command line within a reasonable length to avoid
+            // blowing system limits on limiting platforms like Windows.
+            None => {
+                for chunk in objs.chunks(100) {
+                    self.assemble_progressive(dst, chunk)?;
+                }
+            }
         }
 
         if self.cuda && self.cuda_file_count() > 0 {
-            // Link the device-side code and add it to the target library,
-            // so that non-CUDA linker can link the final binary.
-
-            let out_dir = self.get_out_dir()?;
-            let dlink = out_dir.join(lib_name.to_owned() + "_dlink.o");
-            let mut nvcc = self.get_compiler().to_command();
-            nvcc.arg("--device-link").arg("-o").arg(&dlink).arg(dst);
-            run(&mut nvcc, "nvcc", &self.cargo_output)?;
-            self.assemble_progressive(dst, &[dlink.as_path()])?;
+            let (mut nvcc, dlink) = self.cuda_dlink_command(lib_name, dst)?;
+            self.run_step(&mut nvcc, "nvcc")?;
+            match builtin {
+                Some(format) => {
+                    objs.push(&dlink);
+                    self.assemble_builtin(dst, &objs, format)?;
+                }
+                None => self.assemble_progressive(dst, &[dlink.as_path()])?,
+            }
         }
 
         let target = self.get_target()?;
@@ -2563,29 +4747,293 @@ impl Build {
                 fs::copy(dst, &lib_dst).map(|_| ())
             }) {
                 Ok(_) => (),
-                Err(_) => {
+                Err(e) => {
                     return Err(Error::new(
                         ErrorKind::IOError,
                         "Could not copy or create a hard-link to the generated lib file.",
-                    ));
+                    )
+                    .with_source(e));
                 }
             };
+        } else if builtin.is_none() {
+            let (mut ar, program) = self.ranlib_command(dst)?;
+            self.run_step(&mut ar, &program)?;
+        }
+
+        Ok(())
+    }
+
+    /// Extract the members of the [`Build::archive`]s merged into the library
+    /// `lib_name` into `dst`, renaming those clashing with each other or with
+    /// `objs`.
+    fn extract_archives(
+        &self,
+        dst: &Path,
+        lib_name: &str,
+        objs: &[&Path],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut members = Vec::new();
+        if self.archives.is_empty() {
+            return Ok(members);
+        }
+        let mut taken: HashSet<String> = objs
+            .iter()
+            .filter_map(|obj| obj.file_name())
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .collect();
+        let dir = dst.join("archives").join(lib_name);
+        for archive in &self.archives {
+            let extracted = archive::extract(archive, &dir, &mut taken).map_err(|e| {
+                Error::new(
+                    ErrorKind::IOError,
+                    format!(
+                        "Failed to extract the members of {}: {}",
+                        archive.display(),
+                        e
+                    ),
+                )
+                .with_source(e)
+            })?;
+            members.extend(extracted);
+        }
+        Ok(members)
+    }
+
+    /// Rewrite the objects `objs` of the library `lib_name` for
+    /// [`Build::localize_symbols`] and [`Build::symbol_prefix`], into `dst`,
+    /// returning the new objects, if either is used.
+    fn process_symbols(
+        &self,
+        dst: &Path,
+        lib_name: &str,
+        objs: &[&Path],
+    ) -> Result<Option<Vec<PathBuf>>, Error> {
+        if self.localize_symbols.is_none() && self.symbol_prefix.is_none() {
+            return Ok(None);
+        }
+        let target = self.get_target()?;
+        if target.env == "msvc" {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                "`symbol_prefix` and `localize_symbols` aren't supported for MSVC targets",
+            ));
+        }
+        // The symbols of C functions start with an underscore on these targets.
+        let underscore =
+            if target.vendor == "apple" || (target.os == "windows" && target.arch == "x86") {
+                "_"
+            } else {
+                ""
+            };
+        let dir = dst.join("symbols").join(lib_name);
+        fs::create_dir_all(&dir)?;
+        let mut objs: Vec<PathBuf> = objs.iter().map(|obj| obj.to_path_buf()).collect();
+
+        if let Some(except) = &self.localize_symbols {
+            let list = dir.join(format!("{}.globals", lib_name));
+            let mut globals = String::new();
+            for symbol in except {
+                globals.push_str(underscore);
+                globals.push_str(symbol);
+                globals.push('\n');
+            }
+            fs::write(&list, globals)?;
+
+            let combined = dir.join(format!("{}.o", lib_name));
+            let (mut ld, program) = self.try_get_linker_and_name()?;
+            ld.arg("-r").arg("-nostdlib").arg("-o").arg(&combined);
+            ld.args(&objs);
+            if target.vendor == "apple" {
+                ld.args(["-Xlinker", "-exported_symbols_list", "-Xlinker"]);
+                ld.arg(&list);
+            }
+            self.run_step(&mut ld, &program)?;
+            if target.vendor != "apple" {
+                let (mut objcopy, program) = self.get_objcopy()?;
+                let mut arg = OsString::from("--keep-global-symbols=");
+                arg.push(&list);
+                objcopy.arg(arg).arg(&combined);
+                self.run_step(&mut objcopy, &program)?;
+            }
+            objs = vec![combined];
+        }
+
+        if let Some(prefix) = &self.symbol_prefix {
+            let list = dir.join(format!("{}.redefine", lib_name));
+            let mut renames = String::new();
+            let mut seen = HashSet::new();
+            for obj in &objs {
+                for symbol in archive::symbols(&fs::read(obj)?) {
+                    let symbol = String::from_utf8_lossy(&symbol).into_owned();
+                    let name = symbol.strip_prefix(underscore).unwrap_or(&symbol);
+                    let rename = format!("{} {}{}{}\n", symbol, underscore, prefix, name);
+                    if seen.insert(symbol) {
+                        renames.push_str(&rename);
+                    }
+                }
+            }
+            fs::write(&list, renames)?;
+
+            let mut taken = HashSet::new();
+            let mut renamed = Vec::with_capacity(objs.len());
+            for obj in objs {
+                // The object linked for `localize_symbols` is rewritten in place.
+                let out = if obj.starts_with(&dir) {
+                    obj.clone()
+                } else {
+                    let name = obj.file_name().unwrap_or_default().to_string_lossy();
+                    dir.join(archive::unique_name(&name, &mut taken))
+                };
+                let (mut objcopy, program) = self.get_objcopy()?;
+                let mut arg = OsString::from("--redefine-syms=");
+                arg.push(&list);
+                objcopy.arg(arg).arg(&obj).arg(&out);
+                self.run_step(&mut objcopy, &program)?;
+                renamed.push(out);
+            }
+            objs = renamed;
+        }
+        Ok(Some(objs))
+    }
+
+    /// The `objcopy` rewriting symbols for [`Build::process_symbols`].
+    ///
+    /// GNU `objcopy` can't rewrite Mach-O objects, so unless `OBJCOPY` is
+    /// set, `llvm-objcopy` is used for Apple targets, found next to the
+    /// compiler or in the `PATH`.
+    fn get_objcopy(&self) -> Result<(Command, PathBuf), Error> {
+        if self.get_target()?.vendor != "apple" || self.env_tool("OBJCOPY").is_some() {
+            return self.get_base_archiver_variant("OBJCOPY", "objcopy");
+        }
+        let name = Path::new("llvm-objcopy");
+        let compiler = self.get_base_compiler()?;
+        let path = self
+            .env
+            .iter()
+            .find(|(key, _)| &**key == "PATH")
+            .map(|(_, path)| &**path);
+        let found = if compiler.is_like_clang() {
+            self.search_programs(&mut self.cmd(&compiler.path), name, &self.cargo_output)
         } else {
-            // Non-msvc targets (those using `ar`) need a separate step to add
-            // the symbol table to archives since our construction command of
-            // `cq` doesn't add it for us.
-            let (mut ar, cmd, _any_flags) = self.get_ar()?;
+            None
+        };
+        match found.or_else(|| self.which(name, path)) {
+            Some(program) => Ok((self.cmd(&program), program)),
+            None => Err(Error::new(
+                ErrorKind::ToolNotFound,
+                "`llvm-objcopy` is needed to rewrite the symbols of objects for Apple targets, \
+                 install it or set `OBJCOPY`",
+            )),
+        }
+    }
 
-            // NOTE: We add `s` even if flags were passed using $ARFLAGS/ar_flag, because `s`
-            // here represents a _mode_, not an arbitrary flag. Further discussion of this choice
-            // can be seen in https://github.com/rust-lang/cc-rs/pull/763.
-            run(ar.arg("s").arg(dst), &cmd, &self.cargo_output)?;
+    /// The format of the archive to write with the built-in archiver, if it
+    /// is used instead of `ar`: when `AR=builtin` is set, or when `ar` can't
+    /// be found (and isn't run by a [`Build::runner`], which might find it).
+    fn builtin_archive_format(&self) -> Result<Option<ArchiveFormat>, Error> {
+        let (ar, name, _any_flags) = self.get_ar()?;
+        if name != Path::new("builtin") {
+            if self.runner.is_some() {
+                return Ok(None);
+            }
+            // The archiver is looked up in the `PATH` it runs with.
+            let path = ar
+                .get_envs()
+                .find(|&(key, _)| key == "PATH")
+                .and_then(|(_, path)| path);
+            if self.which(Path::new(ar.get_program()), path).is_some() {
+                return Ok(None);
+            }
+            self.cargo_output.print_warning(&format_args!(
+                "Failed to find `{}`, writing the archive with cc's built-in archiver",
+                name.display()
+            ));
+        }
+        if self.uses_linker_plugin_lto()? {
+            return Err(Error::new(
+                ErrorKind::ToolNotFound,
+                "cc's built-in archiver can't write the symbol table of the LLVM bitcode \
+                 emitted for `-Clinker-plugin-lto`, set `AR` to `llvm-ar` instead",
+            ));
         }
 
+        let target = self.get_target()?;
+        Ok(Some(if target.env == "msvc" {
+            ArchiveFormat::Coff
+        } else if target.vendor == "apple" {
+            ArchiveFormat::Darwin
+        } else {
+            ArchiveFormat::Gnu
+        }))
+    }
+
+    /// Write the archive `dst` containing `objs` with the built-in archiver.
+    fn assemble_builtin(
+        &self,
+        dst: &Path,
+        objs: &[&Path],
+        format: ArchiveFormat,
+    ) -> Result<(), Error> {
+        self.cargo_output.print_debug(&format_args!(
+            "writing {} with the built-in archiver",
+            dst.display()
+        ));
+        let timer = Timer::start(Duration::ZERO);
+        archive::write(dst, objs, format)?;
+        let mut cmd = Command::new("builtin");
+        cmd.args(objs);
+        self.record_archive_timing(timer, dst, &cmd);
         Ok(())
     }
 
+    /// The command linking the device-side code of the CUDA library `dst`,
+    /// and the object it produces, which must be added to the library so
+    /// that non-CUDA linkers can link the final binary.
+    fn cuda_dlink_command(&self, lib_name: &str, dst: &Path) -> Result<(Command, PathBuf), Error> {
+        let out_dir = self.get_out_dir()?;
+        let dlink = out_dir.join(lib_name.to_owned() + "_dlink.o");
+        let mut nvcc = self.try_get_compiler()?.to_command();
+        nvcc.arg("--device-link").arg("-o").arg(&dlink).arg(dst);
+        Ok((nvcc, dlink))
+    }
+
+    /// The command adding the symbol table to the archive `dst`, for targets
+    /// using `ar`.
+    fn ranlib_command(&self, dst: &Path) -> Result<(Command, PathBuf), Error> {
+        // Non-msvc targets (those using `ar`) need a separate step to add
+        // the symbol table to archives since our construction command of
+        // `cq` doesn't add it for us.
+        let (mut ar, program, _any_flags) = self.get_ar()?;
+
+        // NOTE: We add `s` even if flags were passed using $ARFLAGS/ar_flag, because `s`
+        // here represents a _mode_, not an arbitrary flag. Further discussion of this choice
+        // can be seen in https://github.com/rust-lang/cc-rs/pull/763.
+        let mode = if self.ar_deterministic(&program)? {
+            "sD"
+        } else {
+            "s"
+        };
+        ar.arg(mode).arg(dst);
+        Ok((ar, program))
+    }
+
     fn assemble_progressive(&self, dst: &Path, objs: &[&Path]) -> Result<(), Error> {
+        let (mut cmd, program) = self.archive_command(dst, objs, dst.exists())?;
+        let timer = Timer::start(Duration::ZERO);
+        self.run_step(&mut cmd, &program)?;
+        self.record_archive_timing(timer, dst, &cmd);
+
+        Ok(())
+    }
+
+    /// The command adding `objs` to the archive `dst`, which is created
+    /// unless `append`.
+    fn archive_command(
+        &self,
+        dst: &Path,
+        objs: &[&Path],
+        append: bool,
+    ) -> Result<(Command, PathBuf), Error> {
         let target = self.get_target()?;
 
         let (mut cmd, program, any_flags) = self.get_ar()?;
@@ -2600,13 +5048,17 @@ impl Build {
             if !any_flags {
                 cmd.arg("-nologo");
             }
+            // Like `-Brepro` for the compiler, this is undocumented, and
+            // leaves the timestamps out of the library.
+            if self.is_deterministic() {
+                cmd.arg("-Brepro");
+            }
             // If the library file already exists, add the library name
             // as an argument to let lib.exe know we are appending the objs.
-            if dst.exists() {
+            if append {
                 cmd.arg(dst);
             }
             cmd.args(objs);
-            run(&mut cmd, &program, &self.cargo_output)?;
         } else {
             // Set an environment variable to tell the OSX archiver to ensure
             // that all dates listed in the archive are zero, improving
@@ -2635,14 +5087,40 @@ impl Build {
             // NOTE: We add cq here regardless of whether $ARFLAGS/ar_flag have been used because
             // it dictates the _mode_ ar runs in, which the setter of $ARFLAGS/ar_flag can't
             // dictate. See https://github.com/rust-lang/cc-rs/pull/763 for further discussion.
-            run(
-                cmd.arg("cq").arg(dst).args(objs),
-                &program,
-                &self.cargo_output,
-            )?;
+            let mode = if self.ar_deterministic(&program)? {
+                "cqD"
+            } else {
+                "cq"
+            };
+            cmd.arg(mode).arg(dst).args(objs);
         }
 
-        Ok(())
+        Ok((cmd, program))
+    }
+
+    /// Whether builds are deterministic, see [`Build::deterministic`].
+    fn is_deterministic(&self) -> bool {
+        self.deterministic
+            .unwrap_or_else(|| self.getenv("SOURCE_DATE_EPOCH").is_some())
+    }
+
+    /// Whether to pass the `D` modifier, for deterministic archives, to the
+    /// `ar` at `program`.
+    fn ar_deterministic(&self, program: &Path) -> Result<bool, Error> {
+        // Apple's `ar` has no such modifier, but `ZERO_AR_DATE` is always set.
+        Ok(self.is_deterministic()
+            && (self.get_target()?.vendor != "apple"
+                || program.to_string_lossy().contains("llvm-ar")))
+    }
+
+    /// Record how long adding objects to the archive `dst` with `cmd` took,
+    /// and add it to the report requested with `CC_TIMINGS`.
+    fn record_archive_timing(&self, timer: Timer, dst: &Path, cmd: &Command) {
+        if self.timings_path().is_some() {
+            let step = timer.stop("archive", None, dst, cmd, None);
+            self.build_cache.timings.write().unwrap().push(step);
+            self.write_timings();
+        }
     }
 
     fn apple_flags(&self, cmd: &mut Tool) -> Result<(), Error> {
@@ -3140,6 +5618,72 @@ impl Build {
         Ok((cmd, name, any_flags))
     }
 
+    /// Get the linker that's in use for this configuration.
+    ///
+    /// This is the compiler driver with all of its flags for GCC and Clang,
+    /// and `link.exe` (`lld-link` for `clang-cl`) for MSVC. Flags from the
+    /// `LDFLAGS` environment variable are appended.
+    ///
+    /// You can use [`Command::get_program`] to get just the path to the command.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an error occurred while determining the compiler or the
+    /// architecture.
+    pub fn get_linker(&self) -> Command {
+        match self.try_get_linker() {
+            Ok(tool) => tool,
+            Err(e) => fail(&e.message),
+        }
+    }
+
+    /// Get the linker that's in use for this configuration.
+    ///
+    /// This will return a result instead of panicking;
+    /// see [`Self::get_linker`] for the complete description.
+    pub fn try_get_linker(&self) -> Result<Command, Error> {
+        Ok(self.try_get_linker_and_name()?.0)
+    }
+
+    fn try_get_linker_and_name(&self) -> Result<(Command, PathBuf), Error> {
+        let (mut cmd, name) = self.get_base_linker()?;
+        if let Ok(flags) = self.envflags("LDFLAGS") {
+            cmd.args(flags);
+        }
+        Ok((cmd, name))
+    }
+
+    fn get_base_linker(&self) -> Result<(Command, PathBuf), Error> {
+        // Link with the C++ driver if any file is C++, to pull in its runtime.
+        let compiler = if self.uses_cpp() {
+            self.with_language(Language::Cpp).try_get_compiler()?
+        } else {
+            self.try_get_compiler()?
+        };
+        match compiler.family {
+            ToolFamily::Msvc { clang_cl: true } => {
+                let name = PathBuf::from("lld-link");
+                Ok((self.cmd(&name), name))
+            }
+            ToolFamily::Msvc { clang_cl: false } => {
+                let target = self.get_target()?;
+                let cmd = match self.windows_registry_find(&target, "link.exe") {
+                    Some(cmd) => cmd,
+                    None => self.cmd("link.exe"),
+                };
+                Ok((cmd, PathBuf::from("link.exe")))
+            }
+            ToolFamily::Gnu | ToolFamily::Clang { .. } => {
+                let mut cmd = compiler.to_command();
+                for (a, b) in self.env.iter() {
+                    cmd.env(a, b);
+                }
+                self.fix_env_for_apple_os(&mut cmd)?;
+                Ok((cmd, compiler.path().to_owned()))
+            }
+        }
+    }
+
     fn get_base_archiver(&self) -> Result<(Command, PathBuf), Error> {
         if let Some(ref a) = self.archiver {
             let archiver = &**a;
@@ -3196,6 +5740,7 @@ impl Build {
         tool: &str,
     ) -> Result<(Command, PathBuf), Error> {
         let target = self.get_target()?;
+        let linker_plugin_lto = self.uses_linker_plugin_lto()?;
         let mut name = PathBuf::new();
         let tool_opt: Option<Command> = self
             .env_tool(env)
@@ -3235,6 +5780,22 @@ impl Build {
                     } else {
                         None
                     }
+                } else if linker_plugin_lto && target.env != "msvc" {
+                    // The objects are LLVM bitcode, which only the LLVM tools
+                    // know how to index.
+                    let compiler = self.get_base_compiler().ok()?;
+                    if compiler.is_like_clang() {
+                        name = format!("llvm-{}", tool).into();
+                        self.search_programs(
+                            &mut self.cmd(&compiler.path),
+                            &name,
+                            &self.cargo_output,
+                        )
+                        .or_else(|| self.which(&name, None))
+                        .map(|name| self.cmd(name))
+                    } else {
+                        None
+                    }
                 } else {
                     None
                 }
@@ -3590,6 +6151,23 @@ impl Build {
         }
     }
 
+    /// The directory object files mirror the layout of, if
+    /// [`Build::mirror_source_tree`] is enabled.
+    fn object_root(&self) -> Result<Option<PathBuf>, Error> {
+        if !self.mirror_source_tree {
+            return Ok(None);
+        }
+        let cwd = env::current_dir()?;
+        // CARGO_* variables always trigger a rebuild when changed, as in
+        // `objects_from_files`.
+        #[allow(clippy::disallowed_methods)]
+        let root = match &self.source_root {
+            Some(root) => root.to_path_buf(),
+            None => env::var_os("CARGO_MANIFEST_DIR").map_or_else(|| cwd.clone(), PathBuf::from),
+        };
+        Ok(Some(cwd.join(root)))
+    }
+
     #[allow(clippy::disallowed_methods)]
     fn getenv(&self, v: &str) -> Option<Arc<OsStr>> {
         // Returns true for environment variables cargo sets for build scripts:
@@ -4020,6 +6598,34 @@ fn fail(s: &str) -> ! {
     std::process::exit(1);
 }
 
+/// A digest of the contents of the file at `path`, to compare builds.
+fn digest(path: &Path) -> Result<u64, Error> {
+    let mut hasher = hash_map::DefaultHasher::new();
+    hasher.write(&fs::read(path)?);
+    Ok(hasher.finish())
+}
+
+/// Combine the errors of the sources that failed to compile into one.
+fn failures_to_result(mut failures: Vec<(&Path, Error)>) -> Result<(), Error> {
+    if failures.len() <= 1 {
+        return match failures.pop() {
+            Some((_, err)) => Err(err),
+            None => Ok(()),
+        };
+    }
+
+    let mut message = format!("{} sources failed to compile:", failures.len());
+    for (src, err) in &failures {
+        message.push_str(&format!("\n  {}: {}", src.display(), err));
+    }
+    let mut err = Error::new(ErrorKind::ToolExecError, message);
+    err.failures = failures
+        .into_iter()
+        .map(|(src, err)| (src.to_path_buf(), err))
+        .collect();
+    Err(err)
+}
+
 // Use by default minimum available API level
 // See note about naming here
 // https://android.googlesource.com/platform/ndk/+/refs/heads/ndk-release-r21/docs/BuildSystemMaintainers.md#Clang
@@ -4172,6 +6778,52 @@ fn is_disabled() -> bool {
     }
 }
 
+/// A test program for the probes of [`Build::has_header`] and co, including
+/// `headers`, with the declarations `decls` and `body` in `main`.
+fn probe_source(headers: &[&str], decls: &str, body: &str) -> String {
+    let mut source = String::new();
+    for header in headers {
+        source.push_str(&format!("#include <{}>\n", header));
+    }
+    source.push_str(decls);
+    source.push_str("int main(void) {\n");
+    if !body.is_empty() {
+        source.push_str(&format!("    {}\n", body));
+    }
+    source.push_str("    return 0;\n}\n");
+    source
+}
+
+/// Check that the `output` passed to `method` is usable as a file name in
+/// `OUT_DIR`.
+fn check_output_name(output: &str, method: &str) -> Result<(), Error> {
+    let mut output_components = Path::new(output).components();
+    match (output_components.next(), output_components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "argument of `{}` must be a single normal path component",
+                method
+            ),
+        )),
+    }
+}
+
+/// The name of the library compiled by [`Build::compile`] for `output`, and
+/// the file name of the archive.
+fn lib_names(output: &str) -> (&str, String) {
+    if output.starts_with("lib") && output.ends_with(".a") {
+        (&output[3..output.len() - 2], output.to_owned())
+    } else {
+        let mut gnu = String::with_capacity(5 + output.len());
+        gnu.push_str("lib");
+        gnu.push_str(output);
+        gnu.push_str(".a");
+        (output, gnu)
+    }
+}
+
 /// Automates the `if is_disabled() { return error }` check and ensures
 /// we produce a consistent error message for it.
 fn check_disabled() -> Result<(), Error> {
@@ -0,0 +1,76 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The commands [`Build::compile`] would run, without running them, as
+/// returned by [`Build::try_plan`].
+///
+/// [`Build::compile`]: crate::Build::compile
+/// [`Build::try_plan`]: crate::Build::try_plan
+#[derive(Debug)]
+pub struct BuildPlan {
+    pub(crate) objects: Vec<PlannedObject>,
+    pub(crate) archive_commands: Vec<Command>,
+    pub(crate) output: PathBuf,
+    pub(crate) metadata: Vec<String>,
+}
+
+impl BuildPlan {
+    /// The objects to compile, in the order they would be compiled in.
+    ///
+    /// A precompiled header, see
+    /// [`Build::precompiled_header`](crate::Build::precompiled_header), comes
+    /// first, as the other objects depend on it.
+    pub fn objects(&self) -> &[PlannedObject] {
+        &self.objects
+    }
+
+    /// The commands creating the library from the objects, to run in order
+    /// once all of them are compiled.
+    ///
+    /// This is empty when the library is written by the built-in archiver,
+    /// see [`Build::archiver`](crate::Build::archiver).
+    pub fn archive_commands(&self) -> &[Command] {
+        &self.archive_commands
+    }
+
+    /// The path of the library created by the archive commands.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// The lines of cargo metadata (e.g. `cargo:rustc-link-lib=...`) printed
+    /// once the library is created, linking it in.
+    ///
+    /// This is empty if [`Build::cargo_metadata`](crate::Build::cargo_metadata)
+    /// is disabled.
+    pub fn metadata(&self) -> &[String] {
+        &self.metadata
+    }
+}
+
+/// An object to compile, as part of a [`BuildPlan`].
+#[derive(Debug)]
+pub struct PlannedObject {
+    pub(crate) source: PathBuf,
+    pub(crate) object: PathBuf,
+    pub(crate) command: Command,
+}
+
+impl PlannedObject {
+    /// The source file compiled.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The file the command produces.
+    pub fn object(&self) -> &Path {
+        &self.object
+    }
+
+    /// The command compiling the source file.
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    fmt, io,
+    process::{Command, Output},
+};
+
+/// Runs the commands building a library, see [`Build::runner`].
+///
+/// The commands are those compiling objects (and precompiled headers),
+/// creating archives and linking; the commands `cc` runs to find out about
+/// the compiler, such as checking whether it supports a flag, are always run
+/// directly.
+///
+/// This is implemented for closures, so the runner keeping the default
+/// behaviour could be written as:
+///
+/// ```no_run
+/// use std::process::Command;
+///
+/// cc::Build::new()
+///     .file("src/foo.c")
+///     .runner(|cmd: &mut Command| cmd.output())
+///     .compile("foo");
+/// ```
+///
+/// [`Build::runner`]: crate::Build::runner
+pub trait CommandRunner: Send + Sync {
+    /// Run `cmd` to completion, returning what it printed and its exit
+    /// status.
+    ///
+    /// The output is handled as if `cc` ran the command itself: stdout is
+    /// forwarded to the build script's stdout, and stderr is forwarded as
+    /// cargo warnings and parsed for diagnostics. An error of kind
+    /// [`io::ErrorKind::NotFound`] is reported as the tool not being found.
+    ///
+    /// With the `parallel` feature, this is called from several threads at
+    /// once, as many as the jobserver allows.
+    fn run(&self, cmd: &mut Command) -> io::Result<Output>;
+}
+
+impl<F> CommandRunner for F
+where
+    F: Fn(&mut Command) -> io::Result<Output> + Send + Sync,
+{
+    fn run(&self, cmd: &mut Command) -> io::Result<Output> {
+        self(cmd)
+    }
+}
+
+impl fmt::Debug for dyn CommandRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CommandRunner")
+    }
+}
@@ -0,0 +1,315 @@
+//! Recording how long each step of a build takes, for the report requested
+//! with `CC_TIMINGS`.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{fragments, json::Value};
+
+/// A step of the build that is being timed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timer {
+    start: SystemTime,
+    instant: Instant,
+    token_wait: Duration,
+}
+
+impl Timer {
+    /// Start timing a step, which waited `token_wait` for a jobserver token
+    /// before it could start.
+    pub(crate) fn start(token_wait: Duration) -> Timer {
+        Timer {
+            start: SystemTime::now(),
+            instant: Instant::now(),
+            token_wait,
+        }
+    }
+
+    /// Finish timing the step running `cmd` to produce `output`.
+    ///
+    /// `kind` is either `compile`, along with the `source` being compiled and
+    /// the Clang `-ftime-trace` file written for it if any, or `archive`.
+    pub(crate) fn stop(
+        &self,
+        kind: &str,
+        source: Option<&Path>,
+        output: &Path,
+        cmd: &Command,
+        time_trace: Option<&Path>,
+    ) -> Value {
+        let command = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| string(Path::new(arg)))
+            .collect();
+        let start = self
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let mut step = vec![("kind".into(), Value::String(kind.into()))];
+        if let Some(source) = source {
+            step.push(("source".into(), string(source)));
+        }
+        step.extend([
+            ("output".into(), string(output)),
+            ("command".into(), Value::Array(command)),
+            ("start".into(), Value::Number(start)),
+            (
+                "duration".into(),
+                Value::Number(self.instant.elapsed().as_secs_f64()),
+            ),
+            (
+                "token_wait".into(),
+                Value::Number(self.token_wait.as_secs_f64()),
+            ),
+        ]);
+        if let Some(time_trace) = time_trace.filter(|path| path.exists()) {
+            step.push(("time_trace".into(), string(time_trace)));
+        }
+        Value::Object(step)
+    }
+}
+
+fn string(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+/// Add `steps` to the timing report at `path`, keeping the steps recorded by
+/// other builds.
+///
+/// The report is a Chrome `trace_event` file if the file name of `path` ends
+/// with `.trace.json`, and a JSON array of the steps, sorted by start time,
+/// otherwise. Every step is kept in a directory next to the report, with `.d`
+/// appended to its name, see [`fragments::write`]. The steps of outputs which
+/// no longer exist are dropped.
+pub(crate) fn write(path: &Path, steps: &[Value]) -> io::Result<()> {
+    let mut fragments = path.as_os_str().to_owned();
+    fragments.push(".d");
+    let chrome = path.file_name().map_or(false, |name| {
+        name.to_string_lossy().ends_with(".trace.json")
+    });
+
+    let is_stale = |step: &Value| {
+        step.get("output")
+            .and_then(Value::as_str)
+            .map_or(false, |output| !Path::new(output).exists())
+    };
+    fragments::write(
+        Path::new(&fragments),
+        "output",
+        steps,
+        path,
+        is_stale,
+        |steps| {
+            let mut steps: Vec<Value> = steps.iter().filter_map(|s| Value::parse(s)).collect();
+            steps.sort_by(|a, b| number(a, "start").total_cmp(&number(b, "start")));
+            if chrome {
+                chrome_trace(&steps).to_string()
+            } else {
+                let mut report = String::from("[");
+                for step in &steps {
+                    if report.len() > 1 {
+                        report.push(',');
+                    }
+                    report.push_str("\n  ");
+                    report.push_str(&step.to_string());
+                }
+                report.push_str("\n]\n");
+                report
+            }
+        },
+    )
+}
+
+fn number(value: &Value, key: &str) -> f64 {
+    value.get(key).and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+/// Convert `steps`, sorted by start time, into a Chrome `trace_event` file,
+/// laying out the steps running concurrently on separate threads.
+fn chrome_trace(steps: &[Value]) -> Value {
+    // The end of the last step on each thread.
+    let mut threads: Vec<f64> = Vec::new();
+    let mut events = Vec::new();
+    for step in steps {
+        let micros = |key| Value::Number((number(step, key) * 1e6).round());
+        let token_wait = number(step, "token_wait");
+        let start = number(step, "start");
+        let end = start + number(step, "duration");
+        let tid = match threads.iter().position(|&t| t <= start - token_wait) {
+            Some(tid) => tid,
+            None => {
+                threads.push(0.0);
+                threads.len() - 1
+            }
+        };
+        threads[tid] = end;
+
+        let name = step
+            .get("source")
+            .or_else(|| step.get("output"))
+            .and_then(Value::as_str)
+            .map(|path| {
+                let path = Path::new(path);
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_default();
+        let event = |name: String, cat: Value, ts: Value, dur: Value, args: Value| {
+            Value::Object(vec![
+                ("name".into(), Value::String(name)),
+                ("cat".into(), cat),
+                ("ph".into(), Value::String("X".into())),
+                ("ts".into(), ts),
+                ("dur".into(), dur),
+                ("pid".into(), Value::Number(1.0)),
+                ("tid".into(), Value::Number(tid as f64)),
+                ("args".into(), args),
+            ])
+        };
+
+        if token_wait > 0.0 {
+            events.push(event(
+                "waiting for jobserver token".into(),
+                Value::String("jobserver".into()),
+                Value::Number(((start - token_wait) * 1e6).round()),
+                micros("token_wait"),
+                Value::Object(Vec::new()),
+            ));
+        }
+        events.push(event(
+            name,
+            step.get("kind").cloned().unwrap_or(Value::Null),
+            micros("start"),
+            micros("duration"),
+            Value::Object(
+                ["source", "output", "command"]
+                    .iter()
+                    .filter_map(|&key| Some((key.into(), step.get(key)?.clone())))
+                    .collect(),
+            ),
+        ));
+
+        if let Some(time_trace) = step.get("time_trace").and_then(Value::as_str) {
+            events.extend(time_trace_events(
+                Path::new(time_trace),
+                (start * 1e6).round(),
+                tid,
+            ));
+        }
+    }
+    Value::Object(vec![("traceEvents".into(), Value::Array(events))])
+}
+
+/// The events of the `-ftime-trace` file at `path`, moved onto thread `tid`
+/// and shifted to start at `start` (in microseconds).
+fn time_trace_events(path: &Path, start: f64, tid: usize) -> Vec<Value> {
+    let trace = match fs::read_to_string(path).ok().and_then(|s| Value::parse(&s)) {
+        Some(trace) => trace,
+        None => return Vec::new(),
+    };
+    let events = trace
+        .get("traceEvents")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+    events
+        .iter()
+        .filter(|event| {
+            let name = event.get("name").and_then(Value::as_str).unwrap_or("");
+            // Only keep the complete events of the compilation itself, and
+            // not the per-process totals Clang adds on threads of their own.
+            event.get("ph").and_then(Value::as_str) == Some("X") && !name.starts_with("Total ")
+        })
+        .filter_map(|event| match event {
+            Value::Object(fields) => Some(Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match key.as_str() {
+                            "ts" => Value::Number(value.as_f64().unwrap_or(0.0) + start),
+                            "pid" => Value::Number(1.0),
+                            "tid" => Value::Number(tid as f64),
+                            _ => value.clone(),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect(),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The file Clang writes the `-ftime-trace` of an object to.
+pub(crate) fn time_trace_path(obj: &Path) -> PathBuf {
+    obj.with_extension("json")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn report() {
+        let dir = env::temp_dir().join(format!("cc-timings-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let time_trace = dir.join("a.json");
+        fs::write(
+            &time_trace,
+            r#"{"traceEvents":[
+                {"ph":"X","name":"Frontend","ts":10,"dur":5,"pid":7,"tid":7},
+                {"ph":"X","name":"Total Frontend","ts":0,"dur":5,"pid":7,"tid":8},
+                {"ph":"M","name":"process_name","pid":7,"tid":7}
+            ]}"#,
+        )
+        .unwrap();
+        let a = Timer::start(Duration::from_millis(1)).stop(
+            "compile",
+            Some(Path::new("a.c")),
+            &dir.join("a.o"),
+            Command::new("cc").arg("a.c"),
+            Some(&time_trace),
+        );
+        let archive = Timer::start(Duration::ZERO).stop(
+            "archive",
+            None,
+            &dir.join("libfoo.a"),
+            Command::new("ar").arg("cq"),
+            None,
+        );
+        assert_eq!(a.get("time_trace"), Some(&string(&time_trace)));
+        assert_eq!(archive.get("source"), None);
+
+        let report = dir.join("timings.json");
+        write(&report, &[archive.clone(), a.clone()]).unwrap();
+        let steps = Value::parse(&fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(steps.as_array().unwrap(), [a.clone(), archive.clone()]);
+
+        let trace = dir.join("timings.trace.json");
+        write(&trace, &[a, archive]).unwrap();
+        let trace = Value::parse(&fs::read_to_string(&trace).unwrap()).unwrap();
+        let events = trace.get("traceEvents").unwrap().as_array().unwrap();
+        let names: Vec<_> = events
+            .iter()
+            .map(|event| event.get("name").unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["waiting for jobserver token", "a.c", "Frontend", "libfoo.a"]
+        );
+        assert_eq!(events[2].get("tid"), events[1].get("tid"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -169,21 +169,25 @@ impl Tool {
 
             // Ensure all the parent directories exist otherwise temp file creation
             // will fail
-            std::fs::create_dir_all(&out_dir).map_err(|err| Error {
-                kind: ErrorKind::IOError,
-                message: format!("failed to create OUT_DIR '{}': {}", out_dir.display(), err)
-                    .into(),
+            std::fs::create_dir_all(&out_dir).map_err(|err| {
+                Error::new(
+                    ErrorKind::IOError,
+                    format!("failed to create OUT_DIR '{}': {}", out_dir.display(), err),
+                )
+                .with_source(err)
             })?;
 
             let mut tmp =
-                NamedTempfile::new(&out_dir, "detect_compiler_family.c").map_err(|err| Error {
-                    kind: ErrorKind::IOError,
-                    message: format!(
-                        "failed to create detect_compiler_family.c temp file in '{}': {}",
-                        out_dir.display(),
-                        err
+                NamedTempfile::new(&out_dir, "detect_compiler_family.c").map_err(|err| {
+                    Error::new(
+                        ErrorKind::IOError,
+                        format!(
+                            "failed to create detect_compiler_family.c temp file in '{}': {}",
+                            out_dir.display(),
+                            err
+                        ),
                     )
-                    .into(),
+                    .with_source(err)
                 })?;
             let mut tmp_file = tmp.take_file().unwrap();
             tmp_file.write_all(include_bytes!("detect_compiler_family.c"))?;
@@ -2,11 +2,16 @@ use std::{
     cell::UnsafeCell,
     ffi::OsStr,
     fmt::{self, Write},
+    fs, io,
     marker::PhantomData,
     mem::MaybeUninit,
     panic::{RefUnwindSafe, UnwindSafe},
     path::Path,
-    sync::Once,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
 };
 
 pub(super) struct JoinOsStrs<'a, T> {
@@ -49,6 +54,32 @@ where
     }
 }
 
+/// Write `contents` to `path`, unless it already contains them, so that its
+/// modification time only changes when its contents do.
+pub(crate) fn write_if_changed(path: &Path, contents: &[u8]) -> io::Result<()> {
+    match fs::read(path) {
+        Ok(existing) if existing == contents => Ok(()),
+        _ => write_atomic(path, contents),
+    }
+}
+
+/// Replace the file at `path`, so that concurrent readers never observe a
+/// partially written file.
+pub(crate) fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let tmp = path.with_extension(format!(
+        "{}-{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        e
+    })
+}
+
 pub(crate) struct OnceLock<T> {
     once: Once,
     value: UnsafeCell<MaybeUninit<T>>,
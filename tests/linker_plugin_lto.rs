@@ -0,0 +1,104 @@
+#[cfg(unix)]
+use crate::support::Test;
+mod support;
+
+// These tests are in their own module because they modify the environment and would affect other
+// tests when run in parallel with them. They all set it to the same value.
+#[cfg(unix)]
+fn set_rustflags() {
+    std::env::set_var("CARGO_ENCODED_RUSTFLAGS", "-Clinker-plugin-lto");
+}
+
+/// Write a clang-like compiler logging its command lines to `clang.log`, and
+/// finding its programs in the test directory.
+#[cfg(unix)]
+fn clang(test: &Test) -> std::path::PathBuf {
+    test.script_compiler(
+        "clang",
+        &format!(
+            "echo \"$*\" >> {0}/clang.log\n\
+             case \"$*\" in\n\
+             \x20 *detect_compiler_family.c) echo '\"clang\"' ;;\n\
+             \x20 -print-search-dirs) echo 'programs: ={0}' ;;\n\
+             esac\n",
+            test.td.path().display()
+        ),
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn emits_bitcode() {
+    set_rustflags();
+
+    // Emits bitcode for cross-language LTO, and archives it with llvm-ar
+    let test = Test::new();
+    test.shim("llvm-ar");
+    let compiler = clang(&test);
+    let mut build = test.gcc();
+    build.compiler(&compiler).file("foo.c");
+    assert_eq!(
+        build.get_archiver().get_program(),
+        test.td.path().join("llvm-ar")
+    );
+    build.compile("foo");
+    build.compile("foo");
+
+    let log = std::fs::read_to_string(test.td.path().join("clang.log")).unwrap();
+    // llvm-ar is looked for next to clang.
+    assert!(
+        log.lines().any(|line| line == "-print-search-dirs"),
+        "{}",
+        log
+    );
+    let compiles: Vec<_> = log.lines().filter(|line| line.ends_with("foo.c")).collect();
+    assert_eq!(compiles.len(), 2, "{}", log);
+    assert!(
+        compiles
+            .iter()
+            .all(|line| line.split(' ').any(|arg| arg == "-flto=thin")),
+        "{}",
+        log
+    );
+    // The version of LLVM used by clang is only checked once, with the flags
+    // of the build.
+    let version_checks = log
+        .lines()
+        .filter(|line| line.ends_with(" --version"))
+        .count();
+    assert_eq!(version_checks, 1, "{}", log);
+}
+
+#[cfg(unix)]
+#[test]
+fn builtin_archiver() {
+    set_rustflags();
+
+    // The built-in archiver can't index bitcode
+    let test = Test::new();
+    let compiler = clang(&test);
+    let err = test
+        .gcc()
+        .compiler(&compiler)
+        .archiver("builtin")
+        .file("foo.c")
+        .try_compile("foo")
+        .unwrap_err();
+    assert!(err.to_string().contains("llvm-ar"), "{}", err);
+    assert_eq!(err.kind(), cc::ErrorKind::ToolNotFound);
+}
+
+#[cfg(unix)]
+#[test]
+fn gcc() {
+    set_rustflags();
+
+    // GCC can't take part in cross-language LTO
+    let test = Test::gnu();
+    test.shim("llvm-ar");
+    let mut build = test.gcc();
+    build.file("foo.c");
+    assert_eq!(build.get_archiver().get_program(), "ar");
+    build.compile("foo");
+    test.cmd(0).must_not_have("-flto=thin");
+}
@@ -0,0 +1,34 @@
+#[cfg(not(windows))]
+use crate::support::Test;
+mod support;
+
+/// This test is in its own module because it modifies the environment and would affect other tests
+/// when run in parallel with them.
+#[test]
+#[cfg(not(windows))]
+fn remaps_path_prefix() {
+    // Remaps path prefixes like rustc, before the ones given to the build
+    std::env::set_var(
+        "CARGO_ENCODED_RUSTFLAGS",
+        "--remap-path-prefix\u{1f}/src=/a\u{1f}--remap-path-prefix=/home=/b",
+    );
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .remap_path_prefix("/out", "/c")
+        .compile("foo");
+    test.cmd(0)
+        .must_have_in_order("-ffile-prefix-map=/src=/a", "-ffile-prefix-map=/home=/b")
+        .must_have_in_order("-ffile-prefix-map=/home=/b", "-ffile-prefix-map=/out=/c");
+
+    // Unless the flags of rustc aren't inherited
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .inherit_rustflags(false)
+        .remap_path_prefix("/out", "/c")
+        .compile("foo");
+    test.cmd(0)
+        .must_not_have("-ffile-prefix-map=/src=/a")
+        .must_have("-ffile-prefix-map=/out=/c");
+}
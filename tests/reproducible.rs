@@ -0,0 +1,65 @@
+#![allow(clippy::disallowed_methods)]
+
+#[cfg(unix)]
+use crate::support::Test;
+mod support;
+
+/// This test is in its own module because it modifies the environment and would affect other tests
+/// when run in parallel with them.
+#[cfg(unix)]
+#[test]
+fn reproducible() {
+    // A GCC-like compiler writing `SOURCE_DATE_EPOCH` and `stamp` to its
+    // output.
+    let test = Test::gnu();
+    let compiler = |name: &str, stamp: &str| {
+        test.script_compiler(
+            name,
+            &format!(
+                "for arg; do\n\
+                 \x20 if [ \"$prev\" = -o ]; then out=$arg; fi\n\
+                 \x20 prev=$arg\n\
+                 done\n\
+                 if [ -n \"$out\" ]; then echo \"$SOURCE_DATE_EPOCH {}\" > \"$out\"; fi\n",
+                stamp
+            ),
+        )
+    };
+    let stable = compiler("stable-cc", "stable");
+    let unstable = compiler("unstable-cc", "$(date +%N)");
+
+    // SOURCE_DATE_EPOCH is passed to the compiler, and makes archives
+    // deterministic.
+    std::env::set_var("SOURCE_DATE_EPOCH", "1234");
+    test.gcc().compiler(&stable).file("foo.c").compile("foo");
+    let objects: Vec<_> = std::fs::read_dir(test.td.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("o".as_ref()))
+        .collect();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(
+        std::fs::read_to_string(&objects[0]).unwrap(),
+        "1234 stable\n"
+    );
+    test.cmd(0).must_have("cqD");
+    test.cmd(1).must_have("sD");
+    std::env::remove_var("SOURCE_DATE_EPOCH");
+
+    // CC_VERIFY_REPRODUCIBLE compiles every object twice.
+    std::env::set_var("CC_VERIFY_REPRODUCIBLE", "1");
+    test.gcc()
+        .compiler(&stable)
+        .files(["foo.c", "bar.c"])
+        .compile("foo");
+    let err = test
+        .gcc()
+        .compiler(&unstable)
+        .files(["foo.c", "bar.c"])
+        .try_compile("foo")
+        .unwrap_err();
+    std::env::remove_var("CC_VERIFY_REPRODUCIBLE");
+    assert_eq!(err.kind(), cc::ErrorKind::NotReproducible);
+    assert!(err.to_string().contains("foo.c"), "{}", err);
+    assert!(err.to_string().contains("bar.c"), "{}", err);
+}
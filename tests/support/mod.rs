@@ -64,7 +64,7 @@ impl Test {
 
     pub fn msvc() -> Test {
         let mut t = Test::new();
-        t.shim("cl").shim("lib.exe");
+        t.shim("cl").shim("lib.exe").shim("link.exe");
         t.msvc = true;
         t
     }
@@ -85,6 +85,31 @@ impl Test {
         self
     }
 
+    /// Write a shell script named `name` running `body` to the test
+    /// directory, to be used as a compiler. It fails the `-?` probe for
+    /// clang-cl like GCC does.
+    #[cfg(unix)]
+    pub fn script_compiler(&self, name: &str, body: &str) -> PathBuf {
+        self.script(name, "if [ \"$1\" = \"-?\" ]; then exit 1; fi\n", body)
+    }
+
+    /// Like [`Test::script_compiler`], but accepting the `-?` probe like
+    /// MSVC does.
+    #[cfg(unix)]
+    pub fn script_msvc(&self, name: &str, body: &str) -> PathBuf {
+        self.script(name, "if [ \"$1\" = \"-?\" ]; then exit 0; fi\n", body)
+    }
+
+    #[cfg(unix)]
+    fn script(&self, name: &str, probe: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.td.path().join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}{}", probe, body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
     pub fn gcc(&self) -> cc::Build {
         let mut cfg = cc::Build::new();
         let target = if self.msvc {
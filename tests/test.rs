@@ -420,6 +420,1246 @@ fn gnu_std_c() {
     test.cmd(0).must_have("-std=c11");
 }
 
+#[test]
+fn gnu_incremental() {
+    let test = Test::gnu();
+    test.gcc().file("foo.c").incremental(true).compile("foo");
+
+    test.cmd(0)
+        .must_have("-MMD")
+        .must_have("-MF")
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.d"));
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_incremental_rebuild() {
+    reset_env();
+
+    // A GCC-like compiler writing a depfile which lists a header, and logging
+    // every compilation.
+    let test = Test::gnu();
+    let dir = test.td.path();
+    let compiler = test.script_compiler(
+        "incremental-cc",
+        &format!(
+            "for arg; do\n\
+             \x20 case \"$prev\" in -o) out=$arg ;; -MF) dep=$arg ;; esac\n\
+             \x20 if [ \"$arg\" = -c ]; then echo \"$out\" >> {0}/compiles.log; fi\n\
+             \x20 prev=$arg\n\
+             done\n\
+             if [ -n \"$out\" ]; then : > \"$out\"; fi\n\
+             if [ -n \"$dep\" ]; then\n\
+             \x20 echo \"$out: {0}/foo.c {0}/include/foo.h\" > \"$dep\"\n\
+             fi\n",
+            dir.display()
+        ),
+    );
+    std::fs::create_dir(dir.join("include")).unwrap();
+    std::fs::write(dir.join("include/foo.h"), "").unwrap();
+    std::fs::write(dir.join("foo.c"), "").unwrap();
+
+    let build = |define: bool| {
+        let mut build = test.gcc();
+        build
+            .compiler(&compiler)
+            .file(dir.join("foo.c"))
+            .incremental(true);
+        if define {
+            build.define("FOO", None);
+        }
+        build.compile("foo");
+    };
+    let compiles = || {
+        std::fs::read_to_string(dir.join("compiles.log"))
+            .unwrap()
+            .lines()
+            .count()
+    };
+
+    build(false);
+    assert_eq!(compiles(), 1);
+    build(false);
+    assert_eq!(compiles(), 1, "an up-to-date object was recompiled");
+
+    // Make the header newer than the object, regardless of the resolution of
+    // the file system's timestamps.
+    let obj = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension() == Some("o".as_ref()))
+        .unwrap();
+    let header_mtime = std::fs::metadata(dir.join("include/foo.h"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&obj)
+        .unwrap()
+        .set_modified(header_mtime - std::time::Duration::from_secs(2))
+        .unwrap();
+    build(false);
+    assert_eq!(compiles(), 2, "a changed header didn't cause a recompile");
+    build(false);
+    assert_eq!(compiles(), 2);
+
+    // A different command line makes the object out of date too.
+    build(true);
+    assert_eq!(compiles(), 3, "a new define didn't cause a recompile");
+    build(true);
+    assert_eq!(compiles(), 3);
+}
+
+#[test]
+fn gnu_emit_rerun_if_changed() {
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .emit_rerun_if_changed(true)
+        .compile("foo");
+
+    test.cmd(0).must_have("-MMD").must_have("-MF");
+}
+
+/// The cargo metadata is printed to stdout, so this test runs the build in a
+/// child process to capture it.
+#[cfg(unix)]
+#[test]
+fn gnu_emit_rerun_if_changed_metadata() {
+    if let Some(dir) = std::env::var_os("CC_TEST_RERUN_IF_CHANGED_DIR") {
+        let dir = std::path::Path::new(&dir);
+        cc::Build::new()
+            .target("x86_64-unknown-linux-gnu")
+            .host("x86_64-unknown-linux-gnu")
+            .opt_level(2)
+            .debug(false)
+            .out_dir(dir)
+            .compiler(dir.join("depfile-cc"))
+            .file(dir.join("foo.c"))
+            .emit_rerun_if_changed(true)
+            .compile("foo");
+        return;
+    }
+
+    // A GCC-like compiler writing a depfile which lists a header.
+    let test = Test::gnu();
+    let dir = test.td.path();
+    test.script_compiler(
+        "depfile-cc",
+        &format!(
+            "for arg; do\n\
+             \x20 case \"$prev\" in -o) out=$arg ;; -MF) dep=$arg ;; esac\n\
+             \x20 prev=$arg\n\
+             done\n\
+             if [ -n \"$out\" ]; then : > \"$out\"; fi\n\
+             if [ -n \"$dep\" ]; then\n\
+             \x20 echo \"$out: {0}/foo.c {0}/include/foo.h\" > \"$dep\"\n\
+             fi\n",
+            dir.display()
+        ),
+    );
+    std::fs::write(dir.join("foo.c"), "").unwrap();
+
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([
+            "gnu_emit_rerun_if_changed_metadata",
+            "--exact",
+            "--nocapture",
+        ])
+        .env("CC_TEST_RERUN_IF_CHANGED_DIR", dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("cargo:rerun-if-changed="))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            format!("cargo:rerun-if-changed={}", dir.join("foo.c").display()),
+            format!(
+                "cargo:rerun-if-changed={}",
+                dir.join("include/foo.h").display()
+            ),
+        ],
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn gnu_compile_shared() {
+    let test = Test::gnu();
+    let lib = test.gcc().file("foo.c").compile_shared("foo");
+
+    let name = if cfg!(target_os = "macos") {
+        "libfoo.dylib"
+    } else {
+        "libfoo.so"
+    };
+    assert_eq!(lib, test.td.path().join(name));
+    test.cmd(1)
+        .must_have("-o")
+        .must_have(&lib)
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.o"));
+    if cfg!(target_os = "macos") {
+        test.cmd(1)
+            .must_have("-dynamiclib")
+            .must_have("-Wl,-install_name,@rpath/libfoo.dylib");
+    } else {
+        test.cmd(1)
+            .must_have("-shared")
+            .must_have("-Wl,-soname,libfoo.so");
+    }
+}
+
+#[test]
+fn gnu_compile_shared_soname() {
+    let test = Test::gnu();
+    test.gcc()
+        .target("x86_64-unknown-linux-gnu")
+        .file("foo.c")
+        .soname("libfoo.so.1")
+        .compile_shared("foo");
+
+    test.cmd(1).must_have("-Wl,-soname,libfoo.so.1");
+}
+
+#[test]
+fn gnu_link_executable() {
+    reset_env();
+    std::env::remove_var("LDFLAGS");
+
+    let test = Test::gnu();
+    let exe = test.gcc().file("foo.c").link_executable("foo");
+
+    assert_eq!(exe, test.td.path().join("foo"));
+    test.cmd(1)
+        .must_have("-o")
+        .must_have(&exe)
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.o"))
+        .must_not_have("-shared")
+        .must_not_have("-c");
+}
+
+#[test]
+fn gnu_compile_commands() {
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .compile_commands(true)
+        .compile("foo");
+
+    let database = std::fs::read_to_string(test.td.path().join("compile_commands.json")).unwrap();
+    let obj = test.td.path().join("db3b6bfb95261072-foo.o");
+    assert!(database.contains(r#""arguments":["cc","#));
+    assert!(database.contains(&format!(r#""output":"{}""#, obj.display())));
+}
+
+#[test]
+fn gnu_file_with() {
+    reset_env();
+
+    let test = Test::gnu();
+    test.gcc()
+        .define("A", None)
+        .flag("-fwide")
+        .file_with(
+            "foo.c",
+            cc::FileOptions::new()
+                .opt_level(0)
+                .define("B", "1")
+                .flag("-fnarrow"),
+        )
+        .compile("foo");
+
+    test.cmd(0)
+        .must_have("-O0")
+        .must_not_have("-O2")
+        .must_have("-DA")
+        .must_have("-DB=1")
+        .must_have_in_order("-fwide", "-fnarrow");
+}
+
+#[test]
+fn msvc_precompiled_header() {
+    let test = Test::msvc();
+    test.gcc()
+        .file("foo.c")
+        .precompiled_header("foo.h")
+        .compile("foo");
+
+    let header = std::env::current_dir().unwrap().join("foo.h");
+    let mut yc = std::ffi::OsString::from("-Yc");
+    yc.push(&header);
+    let mut yu = std::ffi::OsString::from("-Yu");
+    yu.push(&header);
+    let mut fi = std::ffi::OsString::from("-FI");
+    fi.push(&header);
+    test.cmd(0).must_have(yc);
+    test.cmd(1).must_have(yu).must_have(fi).must_have("foo.c");
+}
+
+// Disable this test with the parallel feature because the execution
+// order is not deterministic.
+#[cfg(not(feature = "parallel"))]
+#[test]
+fn gnu_file_with_other_files() {
+    reset_env();
+
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .file_with("bar.c", cc::FileOptions::new().opt_level(0))
+        .compile("foo");
+
+    test.cmd(0)
+        .must_have("foo.c")
+        .must_have("-O2")
+        .must_not_have("-O0");
+    test.cmd(1)
+        .must_have("bar.c")
+        .must_have("-O0")
+        .must_not_have("-O2");
+}
+
+// Disable this test with the parallel feature because the execution
+// order is not deterministic.
+#[cfg(not(feature = "parallel"))]
+#[test]
+fn gnu_mixed_languages() {
+    reset_env();
+
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .file("bar.cpp")
+        .file_with(
+            "baz.inc",
+            cc::FileOptions::new().language(cc::Language::Cpp),
+        )
+        .std("gnu11")
+        .std_for(cc::Language::Cpp, "c++17")
+        .compile("foo");
+
+    test.cmd(0)
+        .must_have("foo.c")
+        .must_have("-std=gnu11")
+        .must_not_have("-std=c++17");
+    test.cmd(1)
+        .must_have("bar.cpp")
+        .must_have("-std=c++17")
+        .must_not_have("-std=gnu11")
+        .must_not_have("-x");
+    test.cmd(2)
+        .must_have("baz.inc")
+        .must_have("-std=c++17")
+        .must_have_in_order("-x", "c++")
+        .must_have_in_order("c++", "baz.inc");
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_cpp_file_as_c() {
+    reset_env();
+
+    // Compilers logging which of `CC` and `CXX` compiled what.
+    let test = Test::new();
+    test.shim("ar");
+    for name in ["cc", "c++"] {
+        test.script_compiler(
+            name,
+            &format!(
+                "echo {} \"$@\" >> {}/compilers.log\n",
+                name,
+                test.td.path().display()
+            ),
+        );
+    }
+    test.gcc()
+        .file_with("foo.cpp", cc::FileOptions::new().language(cc::Language::C))
+        .std("gnu11")
+        .std_for(cc::Language::Cpp, "c++17")
+        .compile("foo");
+
+    let log = std::fs::read_to_string(test.td.path().join("compilers.log")).unwrap();
+    let compiles: Vec<Vec<_>> = log
+        .lines()
+        .map(|line| line.split(' ').collect())
+        .filter(|args: &Vec<_>| args.contains(&"foo.cpp"))
+        .collect();
+    assert_eq!(compiles.len(), 1, "{}", log);
+    let args = &compiles[0];
+    assert_eq!(args[0], "cc", "{}", log);
+    assert!(args.contains(&"-std=gnu11"), "{}", log);
+    assert!(!args.contains(&"-std=c++17"), "{}", log);
+    assert!(args.windows(2).any(|w| w == ["-x", "c"]), "{}", log);
+}
+
+#[test]
+fn gnu_precompiled_header() {
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .precompiled_header("foo.h")
+        .compile("foo");
+
+    let header = std::env::current_dir().unwrap().join("foo.h");
+    test.cmd(0)
+        .must_have_in_order("-x", "c-header")
+        .must_have(&header);
+    test.cmd(1)
+        .must_have("-include")
+        .must_have("foo.c")
+        .must_not_have(&header);
+
+    // Files compiled with other definitions don't use the precompiled header.
+    let test = Test::gnu();
+    test.gcc()
+        .file_with("foo.c", cc::FileOptions::new().define("FOO", None))
+        .file_with("bar.c", cc::FileOptions::new().unity(false))
+        .precompiled_header("foo.h")
+        .compile("foo");
+    for i in 1..3 {
+        let cmd = test.cmd(i);
+        if cmd.args.iter().any(|arg| arg == "foo.c") {
+            cmd.must_not_have("-include");
+        } else {
+            cmd.must_have("bar.c").must_have("-include");
+        }
+    }
+}
+
+#[test]
+fn gnu_unity_build() {
+    let test = Test::gnu();
+    test.gcc()
+        .file("foo.c")
+        .file("bar.c")
+        .file_with("baz.c", cc::FileOptions::new().unity(false))
+        .unity_build(4)
+        .compile("foo");
+
+    let unity_files = std::fs::read_dir(test.td.path().join("unity"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(unity_files.len(), 1);
+    assert_eq!(unity_files[0].extension().unwrap(), "c");
+    let contents = std::fs::read_to_string(&unity_files[0]).unwrap();
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(
+        contents,
+        format!(
+            "#include \"{}\"\n#include \"{}\"\n",
+            cwd.join("foo.c").display(),
+            cwd.join("bar.c").display()
+        )
+    );
+
+    let (unity, baz) = if test.cmd(0).has("baz.c".as_ref()) {
+        (test.cmd(1), test.cmd(0))
+    } else {
+        (test.cmd(0), test.cmd(1))
+    };
+    unity.must_have(&unity_files[0]).must_not_have("foo.c");
+    baz.must_have("baz.c");
+}
+
+#[test]
+fn error_tool_not_found() {
+    let test = Test::gnu();
+    let err = test
+        .gcc()
+        .compiler(test.td.path().join("does-not-exist"))
+        .file("foo.c")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolNotFound);
+    assert_eq!(
+        err.command().unwrap()[0],
+        test.td.path().join("does-not-exist")
+    );
+    assert_eq!(err.status(), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn error_tool_exec_error() {
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "failing-cc",
+        "echo 'foo.c:1:1: error: expected expression' >&2\nexit 3\n",
+    );
+
+    let err = test
+        .gcc()
+        .compiler(&compiler)
+        .file("foo.c")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolExecError);
+    let command = err.command().unwrap();
+    assert_eq!(command[0], compiler);
+    assert_eq!(command.last().unwrap(), "foo.c");
+    assert_eq!(err.status().unwrap().code(), Some(3));
+    assert_eq!(
+        err.stderr().unwrap(),
+        b"foo.c:1:1: error: expected expression\n"
+    );
+}
+
+#[test]
+fn gnu_plan() {
+    reset_env();
+
+    let test = Test::gnu();
+    let plan = test
+        .gcc()
+        .file("foo.c")
+        .file("bar.c")
+        .try_plan("foo")
+        .unwrap();
+
+    // Nothing was run.
+    assert!(!test.td.path().join("out0").exists());
+
+    let objects = plan.objects();
+    assert_eq!(objects.len(), 2);
+    assert_eq!(objects[0].source(), std::path::Path::new("foo.c"));
+    let args: Vec<_> = objects[0].command().get_args().collect();
+    assert!(args.contains(&"-c".as_ref()));
+    assert!(args.contains(&"foo.c".as_ref()));
+    assert!(args.contains(&objects[0].object().as_os_str()));
+    assert!(!args
+        .iter()
+        .any(|arg| arg.to_string_lossy().starts_with("-fdiagnostics-format")));
+
+    let lib = test.td.path().join("libfoo.a");
+    assert_eq!(plan.output(), lib);
+    let archive: Vec<Vec<_>> = plan
+        .archive_commands()
+        .iter()
+        .map(|cmd| cmd.get_args().collect())
+        .collect();
+    assert_eq!(archive.len(), 2);
+    assert_eq!(archive[0][..2], ["cq".as_ref(), lib.as_os_str()]);
+    assert_eq!(
+        archive[0][2..],
+        [
+            objects[0].object().as_os_str(),
+            objects[1].object().as_os_str()
+        ]
+    );
+    assert_eq!(archive[1], ["s".as_ref(), lib.as_os_str()]);
+
+    assert_eq!(
+        plan.metadata(),
+        [
+            "cargo:rustc-link-lib=static=foo".to_string(),
+            format!(
+                "cargo:rustc-link-search=native={}",
+                test.td.path().display()
+            ),
+        ]
+    );
+
+    let err = test.gcc().file("foo.c").try_plan("a/foo").unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::InvalidArgument);
+    assert!(err.to_string().contains("`try_plan`"), "{}", err);
+}
+
+#[test]
+fn gnu_mirror_source_tree() {
+    reset_env();
+
+    let test = Test::gnu();
+    let plan = test
+        .gcc()
+        .file("src/foo.c")
+        .file("./src/bar/foo.c")
+        .file("/outside/foo.c")
+        .mirror_source_tree(true)
+        .try_plan("foo")
+        .unwrap();
+    let objects: Vec<_> = plan.objects().iter().map(|obj| obj.object()).collect();
+    assert_eq!(objects[0], test.td.path().join("src/foo.c.o"));
+    assert_eq!(objects[1], test.td.path().join("src/bar/foo.c.o"));
+    assert_eq!(objects[2].parent(), Some(test.td.path()));
+    assert!(objects[2].to_str().unwrap().ends_with("-foo.o"));
+
+    // The same source added twice isn't a collision.
+    test.gcc()
+        .file("src/foo.c")
+        .file("lib/foo.c")
+        .file("./src/foo.c")
+        .mirror_source_tree(true)
+        .source_root("src")
+        .try_compile("foo")
+        .unwrap();
+
+    // Generated sources are laid out relative to the output directory.
+    let err = test
+        .gcc()
+        .file("src/foo.c")
+        .file(test.td.path().join("src/foo.c"))
+        .mirror_source_tree(true)
+        .source_root(".")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::InvalidArgument);
+    assert!(err.to_string().contains("would both be compiled to"));
+}
+
+#[test]
+fn gnu_runner() {
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    reset_env();
+
+    let test = Test::gnu();
+    let programs = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&programs);
+    test.gcc()
+        .file("foo.c")
+        .file("bar.c")
+        .runner(move |cmd: &mut Command| {
+            let program = std::path::Path::new(cmd.get_program()).to_owned();
+            recorded.lock().unwrap().push(program);
+            cmd.output()
+        })
+        .compile("foo");
+
+    let mut programs: Vec<_> = programs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|program| program.file_name().unwrap().to_owned())
+        .collect();
+    programs.sort();
+    assert_eq!(programs, ["ar", "ar", "cc", "cc"]);
+
+    let err = test
+        .gcc()
+        .file("foo.c")
+        .runner(|_: &mut Command| Err(std::io::ErrorKind::NotFound.into()))
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolNotFound);
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_builtin_archiver() {
+    reset_env();
+
+    // A GCC-like compiler writing an (empty) object to its output.
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "object-cc",
+        "for arg; do\n\
+         \x20 if [ \"$prev\" = -o ]; then echo objects > \"$arg\"; fi\n\
+         \x20 prev=$arg\n\
+         done\n",
+    );
+
+    let plan = test
+        .gcc()
+        .compiler(&compiler)
+        .archiver("builtin")
+        .file("foo.c")
+        .try_plan("foo")
+        .unwrap();
+    assert!(plan.archive_commands().is_empty());
+
+    test.gcc()
+        .compiler(&compiler)
+        .archiver("builtin")
+        .file("foo.c")
+        .compile("foo");
+    let archive = std::fs::read(test.td.path().join("libfoo.a")).unwrap();
+    assert!(archive.starts_with(b"!<arch>\n"));
+    assert!(archive.ends_with(b"objects\n"));
+    // `ar` wasn't run.
+    assert!(!test.td.path().join("out0").exists());
+}
+
+#[test]
+fn gnu_archive() {
+    reset_env();
+
+    // A GNU archive of two objects both called `foo.o`.
+    let test = Test::gnu();
+    let mut archive = b"!<arch>\n".to_vec();
+    for data in ["foo1", "foo2"] {
+        archive.extend_from_slice(format!("{:<48}{:<10}`\n{}", "foo.o/", 4, data).as_bytes());
+    }
+    let vendor = test.td.path().join("libvendor.a");
+    std::fs::write(&vendor, archive).unwrap();
+
+    test.gcc()
+        .object("bar/foo.o")
+        .archive(&vendor)
+        .file("foo.c")
+        .compile("foo");
+    let members = test.td.path().join("archives").join("foo");
+    test.cmd(1)
+        .must_have("bar/foo.o")
+        .must_have(members.join("foo-1.o"))
+        .must_have(members.join("foo-2.o"));
+    assert_eq!(std::fs::read(members.join("foo-2.o")).unwrap(), b"foo2");
+
+    std::fs::write(&vendor, "not an archive").unwrap();
+    let err = test
+        .gcc()
+        .archive(&vendor)
+        .file("foo.c")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::IOError);
+    assert!(err.to_string().contains("libvendor.a"), "{}", err);
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_probes() {
+    reset_env();
+
+    // A GCC-like compiler for which everything is 8 bytes, which rejects
+    // anything `missing`, and which logs its command lines.
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "probe-cc",
+        "for arg; do src=$arg; done\n\
+         echo \"$*\" >> \"$(dirname \"$0\")/probes.log\"\n\
+         if grep -q missing \"$src\"; then exit 1; fi\n\
+         bound=$(sed -n 's/.*) <= \\([0-9]*\\) ?.*/\\1/p' \"$src\")\n\
+         [ -z \"$bound\" ] || [ \"$bound\" -ge 8 ]\n",
+    );
+
+    let mut build = test.gcc();
+    build
+        .compiler(&compiler)
+        .include("include")
+        .define("FOO", "1");
+    assert!(build.has_header("stdio.h").unwrap());
+    assert!(!build.has_header("missing.h").unwrap());
+    assert_eq!(build.sizeof_type("long", &[]).unwrap(), Some(8));
+    assert_eq!(build.alignof_type("long", &[]).unwrap(), Some(8));
+
+    // The test programs are compiled with the flags of the build, and the
+    // results are cached.
+    let probes = || {
+        std::fs::read_to_string(test.td.path().join("probes.log"))
+            .unwrap()
+            .lines()
+            .filter(|line| line.contains("probes"))
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let compiled = probes();
+    assert!(!compiled.is_empty());
+    for line in &compiled {
+        assert!(
+            line.contains("-DFOO=1") && line.contains(" -c "),
+            "{}",
+            line
+        );
+    }
+    assert_eq!(build.sizeof_type("long", &[]).unwrap(), Some(8));
+    assert_eq!(probes(), compiled);
+
+    assert!(build.has_symbol("stdout", &["stdio.h"]).unwrap());
+    assert!(!build.has_symbol("missing_symbol", &["stdio.h"]).unwrap());
+    assert!(build.has_type("size_t", &["stddef.h"]).unwrap());
+    assert!(!build.has_type("struct missing", &[]).unwrap());
+    assert!(build
+        .has_member("struct stat", "st_size", &["sys/stat.h"])
+        .unwrap());
+    assert!(!build
+        .has_member("struct stat", "st_missing", &["sys/stat.h"])
+        .unwrap());
+
+    // Functions are linked, without the compiler's builtins.
+    assert!(build.has_function("puts").unwrap());
+    assert!(!build.has_function("missing_function").unwrap());
+    let linked = probes()
+        .into_iter()
+        .filter(|line| line.contains("-fno-builtin"))
+        .collect::<Vec<_>>();
+    assert_eq!(linked.len(), 2, "{:?}", probes());
+    for line in &linked {
+        assert!(!line.contains(" -c "), "{}", line);
+        assert!(!line.contains(".o "), "{}", line);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_check_compiles() {
+    reset_env();
+
+    // A GCC-like compiler which only rejects `__int128`, and logs its command
+    // lines.
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "check-cc",
+        "for arg; do src=$arg; done\n\
+         echo \"$*\" >> \"$(dirname \"$0\")/checks.log\"\n\
+         if grep -q __int128 \"$src\"; then\n\
+         \x20 echo \"$src:1:1: error: unknown type name '__int128'\" >&2\n\
+         \x20 exit 1\n\
+         fi\n",
+    );
+
+    let mut build = test.gcc();
+    build.compiler(&compiler).define("FOO", "1");
+    let result = build.check_compiles("int x;", cc::Language::C).unwrap();
+    assert!(result.success());
+    assert!(result.diagnostics().is_empty());
+    let result = build
+        .check_compiles("__int128 x;", cc::Language::C)
+        .unwrap();
+    assert!(!result.success());
+    let diagnostics = result.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity(), cc::Severity::Error);
+    assert_eq!(diagnostics[0].message(), "unknown type name '__int128'");
+    // Checking again hits the cache, and the build's own diagnostics are
+    // left alone.
+    let again = build
+        .check_compiles("__int128 x;", cc::Language::C)
+        .unwrap();
+    assert_eq!(again.diagnostics(), diagnostics);
+    assert!(build.diagnostics().is_empty());
+    assert!(build
+        .check_links("int main(void) { return 0; }", cc::Language::Cpp)
+        .unwrap()
+        .success());
+
+    let checks: Vec<_> = std::fs::read_to_string(test.td.path().join("checks.log"))
+        .unwrap()
+        .lines()
+        .filter(|line| line.contains("probes"))
+        .map(str::to_owned)
+        .collect();
+    assert_eq!(checks.len(), 3, "{:?}", checks);
+    assert!(checks.iter().all(|line| line.contains("-DFOO=1")));
+    assert!(checks[0].contains(" -c ") && checks[0].ends_with(".c"));
+    assert!(!checks[2].contains(" -c ") && checks[2].ends_with(".cpp"));
+}
+
+#[cfg(unix)]
+#[test]
+fn msvc_check_compiles() {
+    reset_env();
+
+    // An MSVC-like compiler which only rejects `__int128`, printing the error
+    // to stdout as `cl` does.
+    let test = Test::msvc();
+    let compiler = test.script_msvc(
+        "check-cl",
+        "for arg; do src=$arg; done\n\
+         echo \"$(basename \"$src\")\"\n\
+         if grep -q __int128 \"$src\"; then\n\
+         \x20 echo \"$src(1): error C4235: nonstandard extension used: '__int128' keyword not supported on this architecture\"\n\
+         \x20 exit 2\n\
+         fi\n",
+    );
+
+    let mut build = test.gcc();
+    build.compiler(&compiler);
+    assert!(build
+        .check_compiles("int x;", cc::Language::C)
+        .unwrap()
+        .success());
+    let result = build
+        .check_compiles("__int128 x;", cc::Language::C)
+        .unwrap();
+    assert!(!result.success());
+    let diagnostics = result.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0].severity(), cc::Severity::Error);
+    assert!(
+        diagnostics[0].message().contains("'__int128' keyword"),
+        "{}",
+        diagnostics[0]
+    );
+}
+
+#[test]
+fn gnu_config_header() {
+    reset_env();
+
+    let test = Test::gnu();
+    let mut build = test.gcc();
+    let mut header = cc::ConfigHeader::new("foo/config.h");
+    header.define("VERSION", "2").define_if("HAVE_BAR", false);
+    let path = header.write(&mut build).unwrap();
+    assert_eq!(path, test.td.path().join("include/foo/config.h"));
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.contains("#define VERSION 2\n"), "{}", contents);
+    assert!(contents.contains("/* #undef HAVE_BAR */\n"), "{}", contents);
+
+    // Writing the same contents again leaves the file alone, and the include
+    // directory is only added once.
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    header.write(&mut build).unwrap();
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().modified().unwrap(),
+        modified
+    );
+    build.file("foo.c").compile("foo");
+    let include = test.td.path().join("include");
+    let args = test.cmd(0).args;
+    let count = args.iter().filter(|arg| *arg == include.to_str().unwrap());
+    assert_eq!(count.count(), 1, "{:?}", args);
+
+    // CMake-style templates are filled in.
+    let template = test.td.path().join("config.h.in");
+    std::fs::write(
+        &template,
+        "#cmakedefine VERSION @VERSION@\n#cmakedefine01 HAVE_BAR\n",
+    )
+    .unwrap();
+    header.template(&template).write(&mut build).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "#define VERSION 2\n#define HAVE_BAR 0\n"
+    );
+
+    let err = cc::ConfigHeader::new("../config.h")
+        .write(&mut build)
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::InvalidArgument);
+}
+
+#[test]
+fn gnu_localize_symbols() {
+    reset_env();
+
+    let test = Test::gnu();
+    test.shim("objcopy");
+    test.gcc()
+        .file("foo.c")
+        .localize_symbols(["foo", "foo_init"])
+        .compile("foo");
+    let dir = test.td.path().join("symbols").join("foo");
+    let globals = dir.join("foo.globals");
+    assert_eq!(
+        std::fs::read_to_string(&globals).unwrap(),
+        "foo\nfoo_init\n"
+    );
+    test.cmd(1)
+        .must_have("-r")
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.o"))
+        .must_have(dir.join("foo.o"));
+    test.cmd(2)
+        .must_have(format!("--keep-global-symbols={}", globals.display()))
+        .must_have(dir.join("foo.o"));
+    test.cmd(3)
+        .must_have(dir.join("foo.o"))
+        .must_not_have(test.td.path().join("db3b6bfb95261072-foo.o"));
+
+    let err = test
+        .gcc()
+        .file("foo.c")
+        .localize_symbols(["foo"])
+        .try_plan("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::InvalidArgument);
+
+    let test = Test::msvc();
+    let err = test
+        .gcc()
+        .file("foo.c")
+        .symbol_prefix("foo_")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::InvalidArgument);
+}
+
+#[cfg(unix)]
+#[test]
+fn gnu_symbol_prefix() {
+    reset_env();
+
+    // A GCC-like compiler writing an ELF object defining `foo` and
+    // `foo_init` to its output.
+    let test = Test::gnu();
+    test.shim("objcopy");
+    let object_cc = |test: &Test, symbols: &[&str]| {
+        let object = test.td.path().join("symbols.o");
+        // Global functions, defined in the first section.
+        let symbols: Vec<_> = symbols.iter().map(|&name| (name, 0x12, 1)).collect();
+        std::fs::write(&object, cc::__elf_object(&symbols)).unwrap();
+        test.script_compiler(
+            "object-cc",
+            &format!(
+                "for arg; do\n\
+                 \x20 if [ \"$prev\" = -o ]; then cp {} \"$arg\"; fi\n\
+                 \x20 prev=$arg\n\
+                 done\n",
+                object.display()
+            ),
+        )
+    };
+    let compiler = object_cc(&test, &["foo", "foo_init"]);
+
+    test.gcc()
+        .target("x86_64-unknown-linux-gnu")
+        .compiler(&compiler)
+        .file("foo.c")
+        .symbol_prefix("v1_")
+        .compile("foo");
+    let dir = test.td.path().join("symbols").join("foo");
+    let redefine = dir.join("foo.redefine");
+    assert_eq!(
+        std::fs::read_to_string(&redefine).unwrap(),
+        "foo v1_foo\nfoo_init v1_foo_init\n"
+    );
+    let object = "db3b6bfb95261072-foo.o";
+    test.cmd(0)
+        .must_have(format!("--redefine-syms={}", redefine.display()))
+        .must_have_in_order(
+            test.td.path().join(object).to_str().unwrap(),
+            dir.join(object).to_str().unwrap(),
+        );
+    test.cmd(1)
+        .must_have(dir.join(object))
+        .must_not_have(test.td.path().join(object));
+
+    // GNU objcopy can't rewrite Mach-O objects, so llvm-objcopy is used for
+    // Apple targets, keeping the leading underscore of the symbols.
+    let test = Test::gnu();
+    let compiler = object_cc(&test, &["_foo"]);
+    test.script_compiler(
+        "llvm-objcopy",
+        "echo \"$*\" >> \"$(dirname \"$0\")/llvm-objcopy.log\"\n",
+    );
+    test.gcc()
+        .target("x86_64-apple-darwin")
+        .compiler(&compiler)
+        .file("foo.c")
+        .symbol_prefix("v1_")
+        .compile("foo");
+    let dir = test.td.path().join("symbols").join("foo");
+    let redefine = dir.join("foo.redefine");
+    assert_eq!(
+        std::fs::read_to_string(&redefine).unwrap(),
+        "_foo _v1_foo\n"
+    );
+    let log = std::fs::read_to_string(test.td.path().join("llvm-objcopy.log")).unwrap();
+    assert!(
+        log.starts_with(&format!("--redefine-syms={}", redefine.display())),
+        "{}",
+        log
+    );
+}
+
+/// The cargo warnings are printed to stdout, so this test runs the build in a
+/// child process to capture them.
+#[cfg(unix)]
+#[test]
+fn diagnostics() {
+    if let Some(compiler) = std::env::var_os("CC_TEST_DIAGNOSTICS_COMPILER") {
+        let compiler = std::path::Path::new(&compiler);
+        let dir = compiler.parent().unwrap();
+        let err = cc::Build::new()
+            .target("x86_64-unknown-linux-gnu")
+            .host("x86_64-unknown-linux-gnu")
+            .opt_level(2)
+            .debug(false)
+            .out_dir(dir)
+            .compiler(compiler)
+            .archiver(dir.join("ar"))
+            .file("foo.c")
+            .try_compile("foo")
+            .unwrap_err();
+        assert_eq!(err.kind(), cc::ErrorKind::ToolExecError);
+        return;
+    }
+
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "warning-cc",
+        "case \"$*\" in\n\
+         \x20 *detect_compiler_family.c) echo '\"gcc\"' ;;\n\
+         \x20 *foo.c)\n\
+         \x20   echo 'In function main:' >&2\n\
+         \x20   echo 'foo.c:2:5: warning: unused variable x [-Wunused-variable]' >&2\n\
+         \x20   echo '    2 |     int x;' >&2\n\
+         \x20   echo 'foo.h:1:1: note: declared here' >&2\n\
+         \x20   echo 'foo.c:3:1: error: expected ;' >&2\n\
+         \x20   exit 1 ;;\n\
+         esac\n",
+    );
+
+    let mut build = test.gcc();
+    build.compiler(&compiler).file("foo.c");
+    let err = build.try_compile("foo").unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolExecError);
+
+    let diagnostics = build.diagnostics();
+    assert_eq!(diagnostics.len(), 2);
+    let warning = &diagnostics[0];
+    assert_eq!(warning.severity(), cc::Severity::Warning);
+    assert_eq!(warning.message(), "unused variable x [-Wunused-variable]");
+    assert_eq!(warning.file(), Some(std::path::Path::new("foo.c")));
+    assert_eq!(warning.line(), Some(2));
+    assert_eq!(warning.column(), Some(5));
+    assert_eq!(warning.notes().len(), 1);
+    assert_eq!(
+        warning.notes()[0].file(),
+        Some(std::path::Path::new("foo.h"))
+    );
+    assert_eq!(warning.notes()[0].message(), "declared here");
+    assert_eq!(
+        warning.rendered(),
+        Some(
+            "In function main:\n\
+             foo.c:2:5: warning: unused variable x [-Wunused-variable]\n\
+             \x20   2 |     int x;\n\
+             foo.h:1:1: note: declared here"
+        )
+    );
+    assert_eq!(diagnostics[1].severity(), cc::Severity::Error);
+    assert_eq!(diagnostics[1].line(), Some(3));
+
+    // Each diagnostic is one cargo warning, however many lines it spans.
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["diagnostics", "--exact", "--nocapture"])
+        .env("CC_TEST_DIAGNOSTICS_COMPILER", &compiler)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let warnings: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("cargo:warning="))
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "cargo:warning=foo.c:2:5: warning: unused variable x [-Wunused-variable]; \
+             foo.h:1:1: note: declared here",
+            "cargo:warning=foo.c:3:1: error: expected ;",
+        ],
+        "{}",
+        stdout
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn machine_readable_diagnostics() {
+    reset_env();
+
+    // A GCC-like compiler which accepts SARIF diagnostics with a warning, and
+    // logs its command lines.
+    let test = Test::gnu();
+    let compiler = test.script_compiler(
+        "sarif-cc",
+        "echo \"$*\" >> \"$(dirname \"$0\")/sarif.log\"\n\
+         case \"$*\" in *-fdiagnostics-format=sarif-stderr*)\n\
+         \x20 echo 'cc1: warning: SARIF output is experimental' >&2 ;;\n\
+         esac\n",
+    );
+    let compiles = || {
+        std::fs::read_to_string(test.td.path().join("sarif.log"))
+            .unwrap()
+            .lines()
+            .filter(|line| line.ends_with("foo.c"))
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+
+    // Text diagnostics are the default.
+    test.gcc().compiler(&compiler).file("foo.c").compile("foo");
+    assert_eq!(compiles().len(), 1);
+    assert!(!compiles()[0].contains("-fdiagnostics-format"));
+
+    let mut build = test.gcc();
+    build
+        .compiler(&compiler)
+        .machine_readable_diagnostics(true)
+        .file("foo.c")
+        .compile("foo");
+    assert!(compiles()[1].contains("-fdiagnostics-format=sarif-stderr"));
+    // Although the flag is used for diagnostics, the warning means it isn't
+    // supported otherwise.
+    assert!(!build
+        .is_flag_supported("-fdiagnostics-format=sarif-stderr")
+        .unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn on_error() {
+    let test = Test::gnu();
+    let compiler = test.script_compiler("failing-cc", "exit 1\n");
+
+    let err = test
+        .gcc()
+        .compiler(&compiler)
+        .files(["foo.c", "bar.c", "baz.c"])
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolExecError);
+    assert!(err.command().is_some(), "{}", err);
+
+    let err = test
+        .gcc()
+        .compiler(&compiler)
+        .files(["foo.c", "bar.c", "baz.c"])
+        .on_error(cc::OnError::KeepGoing)
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::ToolExecError);
+    let message = err.to_string();
+    assert!(
+        message.contains("3 sources failed to compile"),
+        "{}",
+        message
+    );
+    for src in ["foo.c", "bar.c", "baz.c"] {
+        assert!(message.contains(&format!("\n  {}: ", src)), "{}", message);
+    }
+    assert_eq!(err.failures().len(), 3);
+    for (src, failure) in err.failures() {
+        assert_eq!(failure.kind(), cc::ErrorKind::ToolExecError);
+        assert!(
+            failure.command().is_some(),
+            "{}: {}",
+            src.display(),
+            failure
+        );
+        assert!(!failure.status().unwrap().success());
+    }
+}
+
+#[test]
+fn error_source() {
+    let test = Test::gnu();
+    // The output directory can't be created below a file.
+    let file = test.td.path().join("file");
+    std::fs::write(&file, "").unwrap();
+    let err = test
+        .gcc()
+        .out_dir(file.join("out"))
+        .file("foo.c")
+        .try_compile("foo")
+        .unwrap_err();
+    assert_eq!(err.kind(), cc::ErrorKind::IOError);
+    let source = std::error::Error::source(&err).unwrap();
+    assert!(source.downcast_ref::<std::io::Error>().is_some());
+}
+
 #[test]
 fn msvc_smoke() {
     reset_env();
@@ -437,6 +1677,16 @@ fn msvc_smoke() {
         .must_have(test.td.path().join("db3b6bfb95261072-foo.o"));
 }
 
+#[test]
+fn msvc_deterministic() {
+    reset_env();
+
+    let test = Test::msvc();
+    test.gcc().deterministic(true).file("foo.c").compile("foo");
+
+    test.cmd(1).must_have("-Brepro");
+}
+
 #[test]
 fn msvc_opt_level_0() {
     reset_env();
@@ -506,6 +1756,44 @@ fn msvc_std_c() {
     test.cmd(0).must_have("-std:c11");
 }
 
+#[test]
+fn msvc_incremental() {
+    let test = Test::msvc();
+    test.gcc().file("foo.c").incremental(true).compile("foo");
+
+    test.cmd(0)
+        .must_have("-sourceDependencies")
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.json"));
+}
+
+#[test]
+fn msvc_compile_shared() {
+    let test = Test::msvc();
+    let lib = test.gcc().file("foo.c").compile_shared("foo");
+
+    assert_eq!(lib, test.td.path().join("foo.dll"));
+    let mut out = std::ffi::OsString::from("-out:");
+    out.push(&lib);
+    let mut implib = std::ffi::OsString::from("-implib:");
+    implib.push(test.td.path().join("foo.lib"));
+    test.cmd(1)
+        .must_have("-dll")
+        .must_have(out)
+        .must_have(implib)
+        .must_have(test.td.path().join("db3b6bfb95261072-foo.o"));
+}
+
+#[test]
+fn msvc_link_executable() {
+    let test = Test::msvc();
+    let exe = test.gcc().file("foo.c").link_executable("foo");
+
+    assert_eq!(exe, test.td.path().join("foo.exe"));
+    let mut out = std::ffi::OsString::from("-out:");
+    out.push(&exe);
+    test.cmd(1).must_have(out).must_not_have("-dll");
+}
+
 // Disable this test with the parallel feature because the execution
 // order is not deterministic.
 #[cfg(not(feature = "parallel"))]
@@ -0,0 +1,58 @@
+#![allow(clippy::disallowed_methods)]
+
+#[cfg(unix)]
+use crate::support::Test;
+mod support;
+
+/// This test is in its own module because it modifies the environment and would affect other tests
+/// when run in parallel with them.
+#[cfg(unix)]
+#[test]
+fn timings_report() {
+    let test = Test::gnu();
+    // The steps of outputs which don't exist are dropped from the report, so
+    // the objects have to be written.
+    let compiler = test.script_compiler(
+        "objects-cc",
+        "for arg; do\n\
+         \x20 if [ \"$prev\" = -o ]; then echo objects > \"$arg\"; fi\n\
+         \x20 prev=$arg\n\
+         done\n",
+    );
+
+    let report = test.td.path().join("timings.json");
+    std::env::set_var("CC_TIMINGS", &report);
+    test.gcc()
+        .compiler(&compiler)
+        .file("foo.c")
+        .file("bar.c")
+        .compile("foo");
+    std::env::remove_var("CC_TIMINGS");
+
+    let report = std::fs::read_to_string(&report).unwrap();
+    let steps = report
+        .lines()
+        .filter(|line| line.trim_start().starts_with('{'))
+        .collect::<Vec<_>>();
+    assert_eq!(steps.len(), 3, "{}", report);
+    let cwd = std::env::current_dir().unwrap();
+    for src in ["foo.c", "bar.c"] {
+        let source = format!(r#""source":"{}""#, cwd.join(src).display());
+        let step = steps
+            .iter()
+            .find(|step| step.contains(&source))
+            .unwrap_or_else(|| panic!("no step for {} in {}", src, report));
+        assert!(step.contains(r#""kind":"compile""#), "{}", step);
+        assert!(step.contains(r#""duration":"#), "{}", step);
+        assert!(step.contains(&format!("{}.o", &src[..3])), "{}", step);
+    }
+    // Archiving starts after both sources are compiled.
+    let archive = steps.last().unwrap();
+    assert!(archive.contains(r#""kind":"archive""#), "{}", archive);
+    let lib = test.td.path().join("libfoo.a");
+    assert!(
+        archive.contains(&format!(r#""output":"{}""#, lib.display())),
+        "{}",
+        archive
+    );
+}